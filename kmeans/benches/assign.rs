@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nalgebra_glm::{vec4, Vec4};
+
+/// Helical chain of atoms, roughly the shape and density of a protein backbone.
+fn chain(count: usize) -> Vec<Vec4> {
+    (0..count)
+        .map(|i| {
+            let t = i as f32 * 0.3;
+            vec4(10.0 * t.cos(), 10.0 * t.sin(), t * 0.5, 1.5)
+        })
+        .collect()
+}
+
+fn assignment(c: &mut Criterion) {
+    let points = chain(20000);
+    let mut memberships = vec![0; points.len()];
+
+    let mut group = c.benchmark_group("assign");
+    group.sample_size(10);
+    for ratio in [0.1, 0.5, 0.9].iter() {
+        let centroids: Vec<Vec4> = points
+            .iter()
+            .step_by((1.0 / ratio) as usize)
+            .take((points.len() as f32 * ratio) as usize)
+            .copied()
+            .collect();
+
+        group.bench_with_input(
+            BenchmarkId::new("kd_tree", ratio),
+            &centroids,
+            |b, centroids| b.iter(|| kmeans::assign(&points, centroids, &mut memberships)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("brute_force", ratio),
+            &centroids,
+            |b, centroids| {
+                b.iter(|| kmeans::assign_brute_force(&points, centroids, &mut memberships))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn reduction(c: &mut Criterion) {
+    let points = chain(20000);
+
+    c.bench_function("reduce 20000 to 18000", |b| {
+        b.iter(|| kmeans::KMeans::new().with_seed(0).reduce(&points, 18000))
+    });
+}
+
+criterion_group!(benches, assignment, reduction);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+use crate::kdtree::KdTree;
+use crate::{fit_spheres, Reducer, SphereFit};
+
+use nalgebra_glm::{vec4, Vec3, Vec4};
+use rayon::prelude::*;
+
+/// Hierarchical agglomerative clustering with centroid linkage.
+///
+/// Clusters are merged in rounds: each cluster is paired with its nearest neighbour and the pairs are merged
+/// closest first, every cluster at most once per round, until the requested number of clusters remains.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AgglomerativeReducer {
+    /// How the output spheres are fitted to the spheres of their clusters.
+    pub fit: SphereFit,
+}
+
+struct Cluster {
+    centroid: Vec3,
+    points: Vec<usize>,
+}
+
+impl Reducer for AgglomerativeReducer {
+    fn reduce(
+        &self,
+        points: &[Vec4],
+        _residues: Option<&[u32]>,
+        target: usize,
+    ) -> (Vec<Vec4>, Vec<usize>) {
+        let target = target.min(points.len());
+        if target == 0 {
+            return (Vec::new(), vec![0; points.len()]);
+        }
+
+        let mut clusters: Vec<Cluster> = points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| Cluster {
+                centroid: point.xyz(),
+                points: vec![index],
+            })
+            .collect();
+
+        while clusters.len() > target {
+            let centroids: Vec<Vec4> = clusters
+                .iter()
+                .map(|c| vec4(c.centroid.x, c.centroid.y, c.centroid.z, 0.0))
+                .collect();
+            let tree = KdTree::new(&centroids);
+
+            let mut pairs: Vec<(f32, usize, usize)> = (0..clusters.len())
+                .into_par_iter()
+                .filter_map(|i| tree.nearest_other(i).map(|(j, d)| (d, i.min(j), i.max(j))))
+                .collect();
+            pairs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+            let mut merges = clusters.len() - target;
+            let mut merged = vec![false; clusters.len()];
+            for (_, i, j) in pairs {
+                if merges == 0 {
+                    break;
+                }
+                if merged[i] || merged[j] {
+                    continue;
+                }
+
+                let other_centroid = clusters[j].centroid;
+                let other = std::mem::take(&mut clusters[j].points);
+                let cluster = &mut clusters[i];
+                let count = cluster.points.len() as f32;
+                let other_count = other.len() as f32;
+                cluster.centroid = (cluster.centroid * count + other_centroid * other_count)
+                    / (count + other_count);
+                cluster.points.extend(other);
+
+                merged[i] = true;
+                merged[j] = true;
+                merges -= 1;
+            }
+
+            clusters.retain(|c| !c.points.is_empty());
+        }
+
+        let mut memberships = vec![0; points.len()];
+        for (index, cluster) in clusters.iter().enumerate() {
+            for point in cluster.points.iter() {
+                memberships[*point] = index;
+            }
+        }
+
+        (
+            fit_spheres(points, clusters.len(), &memberships, self.fit),
+            memberships,
+        )
+    }
+}
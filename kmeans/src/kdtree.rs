@@ -0,0 +1,94 @@
+use nalgebra_glm::{distance2, Vec4};
+
+/// Static k-d tree over centroids for nearest centroid queries.
+///
+/// The tree is stored implicitly: the median of a slice is its node, the halves before and after it are
+/// its subtrees, and the splitting axis cycles through x, y and z with depth.
+pub struct KdTree<'a> {
+    centroids: &'a [Vec4],
+    nodes: Vec<usize>,
+}
+
+impl<'a> KdTree<'a> {
+    pub fn new(centroids: &'a [Vec4]) -> Self {
+        let mut nodes: Vec<usize> = (0..centroids.len()).collect();
+        build(centroids, &mut nodes, 0);
+
+        Self { centroids, nodes }
+    }
+
+    /// Index of the centroid closest to `point`. Ties resolve to the lowest index, same as a linear search.
+    pub fn nearest(&self, point: &Vec4) -> usize {
+        let mut best = (std::f32::INFINITY, 0);
+        self.search(&self.nodes, 0, point, usize::MAX, &mut best);
+
+        best.1
+    }
+
+    /// Index of the centroid closest to the centroid `index` other than itself, with its squared distance.
+    /// `None` if there is no other centroid.
+    pub fn nearest_other(&self, index: usize) -> Option<(usize, f32)> {
+        let mut best = (std::f32::INFINITY, usize::MAX);
+        self.search(&self.nodes, 0, &self.centroids[index], index, &mut best);
+
+        if best.1 == usize::MAX {
+            None
+        } else {
+            Some((best.1, best.0))
+        }
+    }
+
+    fn search(
+        &self,
+        nodes: &[usize],
+        axis: usize,
+        point: &Vec4,
+        exclude: usize,
+        best: &mut (f32, usize),
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+
+        let mid = nodes.len() / 2;
+        let node = nodes[mid];
+        let centroid = &self.centroids[node];
+
+        let d = distance2(&point.xyz(), &centroid.xyz());
+        if node != exclude && (d < best.0 || (d == best.0 && node < best.1)) {
+            *best = (d, node);
+        }
+
+        let (near, far) = if point[axis] < centroid[axis] {
+            (&nodes[..mid], &nodes[mid + 1..])
+        } else {
+            (&nodes[mid + 1..], &nodes[..mid])
+        };
+
+        let next_axis = (axis + 1) % 3;
+        self.search(near, next_axis, point, exclude, best);
+
+        // Centroids equally distant as the best one have to be visited too, one of them may have a lower index
+        let plane_distance = point[axis] - centroid[axis];
+        if plane_distance * plane_distance <= best.0 {
+            self.search(far, next_axis, point, exclude, best);
+        }
+    }
+}
+
+fn build(centroids: &[Vec4], nodes: &mut [usize], axis: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| {
+        centroids[*a][axis]
+            .partial_cmp(&centroids[*b][axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let (left, right) = nodes.split_at_mut(mid);
+    build(centroids, left, (axis + 1) % 3);
+    build(centroids, &mut right[1..], (axis + 1) % 3);
+}
@@ -1,67 +1,416 @@
+mod agglomerative;
+mod kdtree;
+mod octree;
+mod reducer;
+mod residue;
+mod sphere;
+#[cfg(test)]
+mod test_utils;
+
+pub use agglomerative::AgglomerativeReducer;
+use kdtree::KdTree;
 use nalgebra_glm::{distance, distance2, vec4, zero, Vec3, Vec4};
+pub use octree::OctreeReducer;
 use rand::prelude::*;
+use rand::rngs::StdRng;
 use rayon::prelude::*;
+pub use reducer::{reducer_from_name, Reducer, REDUCER_NAMES};
+pub use residue::ResidueReducer;
+pub use sphere::{enclosing_sphere, volume_preserving_sphere, SphereFit};
 
+/// Reduces `points` to `centroids_num` spheres with the default `KMeans` configuration.
 pub fn reduce(points: &[Vec4], centroids_num: usize) -> Vec<Vec4> {
-    let mut rng = rand::thread_rng();
+    KMeans::default().reduce(points, centroids_num)
+}
 
-    let mut centroids: Vec<Vec4> = Vec::new();
-    let mut memberships: Vec<i32> = vec![0; points.len()];    
+/// Reduces `points` like `reduce`, additionally returning for each point the index of the output sphere it belongs to.
+pub fn reduce_with_memberships(points: &[Vec4], centroids_num: usize) -> (Vec<Vec4>, Vec<usize>) {
+    KMeans::default().reduce_with_memberships(points, centroids_num)
+}
 
-    // Init
-    let centroid_step = (points.len() as f32 / centroids_num as f32).ceil() as usize;
-    for i in 0..centroids_num {
-        if i * centroid_step < points.len() {
-            centroids.push(points[i * centroid_step]);
-        } else {
-            centroids.push(points[(rng.gen::<f32>() * points.len() as f32).floor() as usize]);
+/// K-means clustering of spheres into bounding spheres of the clusters.
+/// Centroids are seeded with k-means++ and refined by Lloyd iterations until they settle.
+#[derive(Copy, Clone, Debug)]
+pub struct KMeans {
+    /// Maximum number of iterations.
+    pub max_iterations: usize,
+
+    /// Iterations stop once no centroid moves further than this distance.
+    pub tolerance: f32,
+
+    /// Seed of the random generator for reproducible results. Seeded from the system when `None`.
+    pub seed: Option<u64>,
+
+    /// How the output spheres are fitted to the spheres of their clusters.
+    pub fit: SphereFit,
+}
+
+impl Default for KMeans {
+    fn default() -> Self {
+        Self {
+            max_iterations: 50,
+            tolerance: 0.01,
+            seed: None,
+            fit: SphereFit::Enclosing,
         }
     }
+}
 
-    // Iterations
-    for _ in 0..5 {
-        // Find centroids
-        points.par_iter().zip_eq(memberships.par_iter_mut()).for_each(|(point, membership)| {
-            let (closest_centroid, _) = centroids.iter().enumerate().fold((0, std::f32::INFINITY), |acc, (i, c)| { 
-                let d = distance2(&point, &c);
-                if d < acc.1 { (i, d) } 
-                else { acc }         
-            });
+impl KMeans {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-            *membership = closest_centroid as i32;
-        });
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_fit(mut self, fit: SphereFit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Returns one sphere per cluster of `points`, fitted according to `fit`.
+    pub fn reduce(&self, points: &[Vec4], centroids_num: usize) -> Vec<Vec4> {
+        self.reduce_with_memberships(points, centroids_num).0
+    }
 
-        // Update centroids
-        centroids.par_iter_mut().enumerate().for_each(|(centroid_index, centroid)| {
-            let mut member_count = 0;
-            let mut bounding_radius = 0.0f32;
-
-            let mut new_centroid: Vec3 = zero();
-            let old_centroid = centroid.clone();
-
-            for i in 0..points.len() {
-                let add_centroid: bool = memberships[i] == centroid_index as i32;
-                let inc_point: Vec4 = if add_centroid { points[i] } else { zero() };
-
-                new_centroid += inc_point.xyz();
-                member_count += if add_centroid { 1 } else { 0 };
-                bounding_radius = if add_centroid {
-                    if distance(&old_centroid.xyz(), &inc_point.xyz()) + inc_point[3] > bounding_radius {
-                        distance(&old_centroid.xyz(), &inc_point.xyz()) + inc_point[3]
-                    } else {
-                        bounding_radius
-                    }
-                } else {
-                    bounding_radius
-                };
+    /// Reduces `points` like `reduce`, additionally returning for each point the index of the output sphere it belongs to.
+    ///
+    /// Returns exactly `centroids_num` spheres, or one per point if there are fewer points than that.
+    pub fn reduce_with_memberships(
+        &self,
+        points: &[Vec4],
+        centroids_num: usize,
+    ) -> (Vec<Vec4>, Vec<usize>) {
+        let centroids_num = centroids_num.min(points.len());
+        if centroids_num == 0 {
+            return (Vec::new(), vec![0; points.len()]);
+        }
+
+        let mut rng = self.rng();
+        let mut centroids = seed_centroids(points, centroids_num, &mut rng);
+        let mut memberships: Vec<usize> = vec![0; points.len()];
+
+        for _ in 0..self.max_iterations.max(1) {
+            assign(points, &centroids, &mut memberships);
+            let reseeded = fill_empty_clusters(points, &mut centroids, &mut memberships);
+
+            // Move centroids to the mean of their points
+            let mut sums: Vec<Vec3> = vec![zero(); centroids.len()];
+            let mut counts = vec![0usize; centroids.len()];
+            for (point, membership) in points.iter().zip(memberships.iter()) {
+                sums[*membership] += point.xyz();
+                counts[*membership] += 1;
+            }
+
+            let mut max_shift = 0.0f32;
+            for (centroid, (sum, count)) in centroids.iter_mut().zip(sums.iter().zip(counts.iter()))
+            {
+                let new_centroid = sum / *count as f32;
+                max_shift = max_shift.max(distance(&centroid.xyz(), &new_centroid));
+                *centroid = vec4(new_centroid.x, new_centroid.y, new_centroid.z, 0.0);
+            }
+
+            if !reseeded && max_shift <= self.tolerance {
+                break;
+            }
+        }
+
+        (
+            fit_spheres(points, centroids.len(), &memberships, self.fit),
+            memberships,
+        )
+    }
+}
+
+/// Picks the initial centroids by k-means++: each next centroid is a point chosen with probability
+/// proportional to its squared distance from the closest centroid picked so far.
+fn seed_centroids(points: &[Vec4], centroids_num: usize, rng: &mut StdRng) -> Vec<Vec4> {
+    let mut centroids = Vec::with_capacity(centroids_num);
+    centroids.push(points[rng.gen_range(0..points.len())]);
+
+    let mut distances: Vec<f32> = points
+        .par_iter()
+        .map(|point| distance2(&point.xyz(), &centroids[0].xyz()))
+        .collect();
+
+    while centroids.len() < centroids_num {
+        let total: f64 = distances.iter().map(|d| *d as f64).sum();
+
+        let next = if total > 0.0 {
+            // Falls back to the last point not yet chosen in case of rounding errors
+            let mut target = rng.gen::<f64>() * total;
+            let mut chosen = distances.iter().rposition(|d| *d > 0.0).unwrap();
+            for (i, d) in distances.iter().enumerate() {
+                target -= *d as f64;
+                if target < 0.0 {
+                    chosen = i;
+                    break;
+                }
             }
+            chosen
+        } else {
+            // All points coincide with the centroids
+            rng.gen_range(0..points.len())
+        };
+
+        let centroid = points[next];
+        centroids.push(centroid);
+
+        distances
+            .par_iter_mut()
+            .zip(points.par_iter())
+            .for_each(|(d, point)| {
+                *d = d.min(distance2(&point.xyz(), &centroid.xyz()));
+            });
+    }
+
+    centroids
+}
 
-            new_centroid = new_centroid * (1.0 / member_count as f32);
+/// Number of centroids from which the assignment searches a k-d tree instead of all centroids.
+const KD_TREE_THRESHOLD: usize = 32;
 
-            *centroid = vec4(new_centroid[0], new_centroid[1], new_centroid[2], bounding_radius);
+/// Assigns each point to its closest centroid. Ties resolve to the centroid with the lowest index.
+pub fn assign(points: &[Vec4], centroids: &[Vec4], memberships: &mut [usize]) {
+    if centroids.len() < KD_TREE_THRESHOLD {
+        return assign_brute_force(points, centroids, memberships);
+    }
+
+    let tree = KdTree::new(centroids);
+    points
+        .par_iter()
+        .zip_eq(memberships.par_iter_mut())
+        .for_each(|(point, membership)| {
+            *membership = tree.nearest(point);
+        });
+}
+
+/// Assigns each point to its closest centroid by testing all centroids.
+pub fn assign_brute_force(points: &[Vec4], centroids: &[Vec4], memberships: &mut [usize]) {
+    points
+        .par_iter()
+        .zip_eq(memberships.par_iter_mut())
+        .for_each(|(point, membership)| {
+            let (closest_centroid, _) =
+                centroids
+                    .iter()
+                    .enumerate()
+                    .fold((0, std::f32::INFINITY), |acc, (i, c)| {
+                        let d = distance2(&point.xyz(), &c.xyz());
+                        if d < acc.1 {
+                            (i, d)
+                        } else {
+                            acc
+                        }
+                    });
+
+            *membership = closest_centroid;
         });
+}
+
+/// Re-seeds clusters that lost all their points by splitting the clusters with the largest error: the point
+/// of such cluster furthest from its centroid becomes the only member and the centroid of the empty cluster.
+/// Requires at least as many points as centroids. Returns whether any cluster was empty.
+fn fill_empty_clusters(points: &[Vec4], centroids: &mut [Vec4], memberships: &mut [usize]) -> bool {
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); centroids.len()];
+    for (point, membership) in memberships.iter().enumerate() {
+        members[*membership].push(point);
+    }
+
+    let empty: Vec<usize> = (0..centroids.len())
+        .filter(|c| members[*c].is_empty())
+        .collect();
+    if empty.is_empty() {
+        return false;
+    }
+
+    let error = |cluster: usize, members: &[usize], centroids: &[Vec4]| -> f32 {
+        members
+            .iter()
+            .map(|p| distance2(&points[*p].xyz(), &centroids[cluster].xyz()))
+            .sum()
+    };
+    let mut errors: Vec<f32> = (0..centroids.len())
+        .map(|c| error(c, &members[c], centroids))
+        .collect();
+
+    for empty_cluster in empty {
+        // Clusters of a single point can not be split. Clusters of coincident points have zero error,
+        // but still have to give away points when there is nothing else left to split.
+        let split_cluster = (0..centroids.len())
+            .filter(|c| members[*c].len() > 1)
+            .max_by(|a, b| {
+                errors[*a]
+                    .partial_cmp(&errors[*b])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .expect("more centroids than points");
+
+        let centroid = centroids[split_cluster];
+        let (position, _) = members[split_cluster]
+            .iter()
+            .enumerate()
+            .map(|(position, p)| (position, distance2(&points[*p].xyz(), &centroid.xyz())))
+            .fold((0, -1.0f32), |acc, x| if x.1 > acc.1 { x } else { acc });
+        let point = members[split_cluster].swap_remove(position);
+
+        memberships[point] = empty_cluster;
+        members[empty_cluster].push(point);
+        centroids[empty_cluster] = points[point];
+
+        errors[split_cluster] = error(split_cluster, &members[split_cluster], centroids);
+        errors[empty_cluster] = 0.0;
+    }
+
+    true
+}
+
+/// Fits a sphere to the points of each cluster.
+pub(crate) fn fit_spheres(
+    points: &[Vec4],
+    clusters_num: usize,
+    memberships: &[usize],
+    fit: SphereFit,
+) -> Vec<Vec4> {
+    let mut clusters: Vec<Vec<Vec4>> = vec![Vec::new(); clusters_num];
+    for (point, membership) in points.iter().zip(memberships.iter()) {
+        clusters[*membership].push(*point);
     }
 
-    centroids.into_iter().filter(|v| v[3] > 0.0).collect()
+    clusters
+        .par_iter()
+        .map(|cluster| fit.fit(cluster))
+        .collect()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::chain;
+
+    #[test]
+    fn kd_tree_assignment_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let points = chain(5000);
+
+        for centroids_num in [1, 31, 32, 100, 1000, 4500].iter() {
+            let centroids = seed_centroids(&points, *centroids_num, &mut rng);
+
+            let mut expected = vec![0; points.len()];
+            let mut actual = vec![0; points.len()];
+            assign_brute_force(&points, &centroids, &mut expected);
+            assign(&points, &centroids, &mut actual);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn kd_tree_resolves_ties_to_lowest_index() {
+        // Grid points are equally distant from several centroids on the same grid
+        let points: Vec<Vec4> = (0..1000)
+            .map(|i| vec4((i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32, 1.0))
+            .collect();
+        let centroids: Vec<Vec4> = points
+            .iter()
+            .rev()
+            .step_by(3)
+            .map(|p| p + vec4(0.5, 0.5, 0.0, 0.0))
+            .collect();
+
+        let mut expected = vec![0; points.len()];
+        let mut actual = vec![0; points.len()];
+        assign_brute_force(&points, &centroids, &mut expected);
+        assign(&points, &centroids, &mut actual);
+
+        assert_eq!(expected, actual);
+    }
+
+    /// Checks that the reduction returns the requested number of finite spheres bounding their member points.
+    fn check_reduction(points: &[Vec4], centroids_num: usize, seed: u64) {
+        let (spheres, memberships) = KMeans::new()
+            .with_seed(seed)
+            .reduce_with_memberships(points, centroids_num);
+
+        assert_eq!(spheres.len(), centroids_num.min(points.len()));
+        assert_eq!(memberships.len(), points.len());
+        if spheres.is_empty() {
+            return;
+        }
+        assert!(spheres.iter().all(|s| s.iter().all(|v| v.is_finite())));
+
+        let mut counts = vec![0; spheres.len()];
+        for (point, membership) in points.iter().zip(memberships.iter()) {
+            let sphere = spheres[*membership];
+            assert!(distance(&sphere.xyz(), &point.xyz()) + point.w <= sphere.w + 1e-3);
+            counts[*membership] += 1;
+        }
+        assert!(counts.iter().all(|c| *c > 0));
+    }
+
+    #[test]
+    fn reduction_returns_requested_number_of_spheres() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for seed in 0..50 {
+            let count = rng.gen_range(1..300);
+            let points: Vec<Vec4> = (0..count)
+                .map(|_| {
+                    vec4(
+                        rng.gen_range(-20.0..20.0),
+                        rng.gen_range(-20.0..20.0),
+                        rng.gen_range(-20.0..20.0),
+                        1.5,
+                    )
+                })
+                .collect();
+            let centroids_num = rng.gen_range(1..=count);
+
+            check_reduction(&points, centroids_num, seed);
+        }
+    }
+
+    #[test]
+    fn reduction_of_degenerate_inputs() {
+        let coincident = vec![vec4(1.0, 2.0, 3.0, 1.0); 50];
+        let collinear: Vec<Vec4> = (0..100).map(|i| vec4(i as f32, 0.0, 0.0, 0.5)).collect();
+        let zero_radii: Vec<Vec4> = (0..100)
+            .map(|i| vec4((i % 3) as f32, 0.0, 0.0, 0.0))
+            .collect();
+        let clustered: Vec<Vec4> = (0..100)
+            .map(|i| vec4(if i < 95 { 0.0 } else { 100.0 + i as f32 }, 0.0, 0.0, 1.0))
+            .collect();
+
+        for points in [coincident, collinear, zero_radii, clustered].iter() {
+            for centroids_num in
+                [1, 2, 10, points.len() - 1, points.len(), points.len() + 10].iter()
+            {
+                check_reduction(points, *centroids_num, 0);
+            }
+        }
+
+        check_reduction(&[vec4(0.0, 0.0, 0.0, 1.0)], 1, 0);
+        check_reduction(&[], 5, 0);
+        check_reduction(&chain(100), 0, 0);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{fit_spheres, Reducer, SphereFit};
+
+use nalgebra_glm::{max2, min2, vec3, Vec3, Vec4};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Depth at which cells are no longer split, cells are then smaller than the precision of coordinates.
+const MAX_DEPTH: u32 = 24;
+
+/// Adaptive octree clustering. Starting from the bounding cube of all points, the cell with the most points
+/// is repeatedly split into its octants until there are as many non-empty cells as requested spheres.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OctreeReducer {
+    /// How the output spheres are fitted to the spheres of their cells.
+    pub fit: SphereFit,
+}
+
+struct Cell {
+    min: Vec3,
+    size: f32,
+    depth: u32,
+    points: Vec<usize>,
+}
+
+impl Cell {
+    fn split(&self, points: &[Vec4]) -> Vec<Cell> {
+        let size = self.size * 0.5;
+        let center = self.min + vec3(size, size, size);
+
+        let mut octants: Vec<Vec<usize>> = vec![Vec::new(); 8];
+        for point in self.points.iter() {
+            let p = points[*point];
+            let octant = (p.x >= center.x) as usize
+                | ((p.y >= center.y) as usize) << 1
+                | ((p.z >= center.z) as usize) << 2;
+            octants[octant].push(*point);
+        }
+
+        octants
+            .into_iter()
+            .enumerate()
+            .filter(|(_, points)| !points.is_empty())
+            .map(|(octant, points)| Cell {
+                min: self.min
+                    + vec3(
+                        (octant & 1) as f32 * size,
+                        (octant >> 1 & 1) as f32 * size,
+                        (octant >> 2 & 1) as f32 * size,
+                    ),
+                size,
+                depth: self.depth + 1,
+                points,
+            })
+            .collect()
+    }
+}
+
+impl Reducer for OctreeReducer {
+    fn reduce(
+        &self,
+        points: &[Vec4],
+        _residues: Option<&[u32]>,
+        target: usize,
+    ) -> (Vec<Vec4>, Vec<usize>) {
+        let target = target.min(points.len());
+        if target == 0 {
+            return (Vec::new(), vec![0; points.len()]);
+        }
+
+        let (min, max) = points
+            .iter()
+            .fold((points[0].xyz(), points[0].xyz()), |(min, max), p| {
+                (min2(&min, &p.xyz()), max2(&max, &p.xyz()))
+            });
+        let extent = max - min;
+        let size = extent.x.max(extent.y).max(extent.z).max(std::f32::EPSILON);
+
+        let mut cells = vec![Cell {
+            min,
+            size,
+            depth: 0,
+            points: (0..points.len()).collect(),
+        }];
+        let mut leaves = Vec::new();
+
+        // Cells by the number of their points, ties broken by the order of creation
+        let mut queue = BinaryHeap::new();
+        queue.push((points.len(), Reverse(0)));
+        let mut cells_count = 1;
+
+        while let Some((_, Reverse(index))) = queue.pop() {
+            let cell = &cells[index];
+            if cells_count == target || cell.points.len() == 1 || cell.depth >= MAX_DEPTH {
+                leaves.push(index);
+                continue;
+            }
+
+            let children = cell.split(points);
+            if cells_count - 1 + children.len() > target {
+                leaves.push(index);
+                continue;
+            }
+
+            cells_count += children.len() - 1;
+            cells[index].points = Vec::new();
+            for child in children {
+                queue.push((child.points.len(), Reverse(cells.len())));
+                cells.push(child);
+            }
+        }
+
+        let mut memberships = vec![0; points.len()];
+        for (leaf_index, leaf) in leaves.iter().enumerate() {
+            for point in cells[*leaf].points.iter() {
+                memberships[*point] = leaf_index;
+            }
+        }
+
+        (
+            fit_spheres(points, leaves.len(), &memberships, self.fit),
+            memberships,
+        )
+    }
+}
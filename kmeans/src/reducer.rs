@@ -0,0 +1,130 @@
+use crate::{AgglomerativeReducer, KMeans, OctreeReducer, ResidueReducer};
+
+use nalgebra_glm::Vec4;
+
+/// Simplification of a set of spheres into fewer spheres.
+pub trait Reducer: Sync {
+    /// Reduces `points` to about `target` spheres. Returns the spheres and for each point the index of the sphere
+    /// it belongs to.
+    ///
+    /// `residues` optionally holds for each point the identifier of its residue, for reducers that group atoms by
+    /// residues. Points of the same residue are expected to be consecutive.
+    fn reduce(
+        &self,
+        points: &[Vec4],
+        residues: Option<&[u32]>,
+        target: usize,
+    ) -> (Vec<Vec4>, Vec<usize>);
+}
+
+impl Reducer for KMeans {
+    fn reduce(
+        &self,
+        points: &[Vec4],
+        _residues: Option<&[u32]>,
+        target: usize,
+    ) -> (Vec<Vec4>, Vec<usize>) {
+        self.reduce_with_memberships(points, target)
+    }
+}
+
+/// Names of the reducers accepted by `reducer_from_name`.
+pub const REDUCER_NAMES: &[&str] = &["kmeans", "octree", "agglomerative", "residue"];
+
+/// Reducer with default settings by its name, for command line tools. `seed` makes randomized reducers reproducible.
+pub fn reducer_from_name(name: &str, seed: Option<u64>) -> Result<Box<dyn Reducer>, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "kmeans" | "k-means" => Ok(Box::new(KMeans {
+            seed,
+            ..KMeans::default()
+        })),
+        "octree" => Ok(Box::new(OctreeReducer::default())),
+        "agglomerative" => Ok(Box::new(AgglomerativeReducer::default())),
+        "residue" => Ok(Box::new(ResidueReducer::default())),
+        _ => Err(format!(
+            "unknown reducer {}, expected one of {}",
+            name,
+            REDUCER_NAMES.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::chain;
+    use nalgebra_glm::distance;
+
+    #[test]
+    fn reducers_enclose_their_members() {
+        let points = chain(2000);
+        let residues: Vec<u32> = (0..points.len() as u32).map(|i| i / 8).collect();
+
+        for name in REDUCER_NAMES {
+            let reducer = reducer_from_name(name, Some(0)).unwrap();
+
+            for target in [1, 7, 100, 250, 1999, 2000, 5000].iter() {
+                let (spheres, memberships) = reducer.reduce(&points, Some(&residues), *target);
+
+                assert!(
+                    !spheres.is_empty() && spheres.len() <= *target,
+                    "{} {}",
+                    name,
+                    target
+                );
+                assert_eq!(memberships.len(), points.len());
+
+                let mut counts = vec![0; spheres.len()];
+                for (point, membership) in points.iter().zip(memberships.iter()) {
+                    let sphere = spheres[*membership];
+                    assert!(distance(&sphere.xyz(), &point.xyz()) + point.w <= sphere.w + 1e-3);
+                    counts[*membership] += 1;
+                }
+                assert!(counts.iter().all(|c| *c > 0), "{} {}", name, target);
+            }
+
+            assert!(reducer.reduce(&[], None, 10).0.is_empty());
+        }
+    }
+
+    #[test]
+    fn clustering_reducers_return_requested_count() {
+        let points = chain(2000);
+
+        for reducer in [
+            Box::new(KMeans::default()) as Box<dyn Reducer>,
+            Box::new(AgglomerativeReducer::default()),
+        ]
+        .iter()
+        {
+            for target in [1, 7, 100, 1999].iter() {
+                assert_eq!(reducer.reduce(&points, None, *target).0.len(), *target);
+            }
+        }
+
+        // Octree cells are split into up to 8 cells at once
+        let octree = OctreeReducer::default().reduce(&points, None, 100).0.len();
+        assert!(octree > 100 - 8 && octree <= 100);
+    }
+
+    #[test]
+    fn residue_reducer_keeps_residues_together() {
+        let points = chain(100);
+        let residues: Vec<u32> = (0..100).map(|i| i / 10).collect();
+        let reducer = ResidueReducer::default();
+
+        let (spheres, memberships) = reducer.reduce(&points, Some(&residues), 50);
+        assert_eq!(spheres.len(), 10);
+        assert!(memberships
+            .iter()
+            .zip(residues.iter())
+            .all(|(m, r)| *m == *r as usize));
+
+        let (spheres, memberships) = reducer.reduce(&points, Some(&residues), 5);
+        assert_eq!(spheres.len(), 5);
+        assert!(memberships
+            .iter()
+            .zip(residues.iter())
+            .all(|(m, r)| *m == *r as usize / 2));
+    }
+}
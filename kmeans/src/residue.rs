@@ -0,0 +1,52 @@
+use crate::{fit_spheres, Reducer, SphereFit};
+
+use nalgebra_glm::Vec4;
+
+/// Coarse-graining by residues: the atoms of each residue form one sphere. When fewer spheres than residues are
+/// requested, consecutive residues are grouped evenly. There are never more spheres than residues.
+///
+/// Points without residues are treated as residues of their own, so consecutive points are grouped instead.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ResidueReducer {
+    /// How the output spheres are fitted to the atoms of their residues.
+    pub fit: SphereFit,
+}
+
+impl Reducer for ResidueReducer {
+    fn reduce(
+        &self,
+        points: &[Vec4],
+        residues: Option<&[u32]>,
+        target: usize,
+    ) -> (Vec<Vec4>, Vec<usize>) {
+        let target = target.min(points.len());
+        if target == 0 {
+            return (Vec::new(), vec![0; points.len()]);
+        }
+
+        // Index of the run of consecutive points of the same residue for each point
+        let mut runs = Vec::with_capacity(points.len());
+        let mut runs_count = 0;
+        for index in 0..points.len() {
+            let new_run = match residues {
+                Some(residues) => index == 0 || residues[index] != residues[index - 1],
+                None => true,
+            };
+            if new_run {
+                runs_count += 1;
+            }
+            runs.push(runs_count - 1);
+        }
+
+        let groups_count = target.min(runs_count);
+        let memberships: Vec<usize> = runs
+            .into_iter()
+            .map(|run| run * groups_count / runs_count)
+            .collect();
+
+        (
+            fit_spheres(points, groups_count, &memberships, self.fit),
+            memberships,
+        )
+    }
+}
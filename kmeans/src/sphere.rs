@@ -0,0 +1,212 @@
+use nalgebra_glm::{distance, vec4, zero, Vec3, Vec4};
+
+/// Number of refinement steps of the enclosing sphere after the initial Ritter sphere.
+const REFINEMENT_ITERATIONS: usize = 64;
+
+/// How a sphere replacing a cluster of spheres is fitted.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SphereFit {
+    /// Near-minimal sphere enclosing all spheres of the cluster.
+    Enclosing,
+    /// Sphere at the centroid of the cluster with the same volume as all spheres of the cluster together.
+    /// It does not necessarily enclose the spheres.
+    VolumePreserving,
+}
+
+impl Default for SphereFit {
+    fn default() -> Self {
+        SphereFit::Enclosing
+    }
+}
+
+impl SphereFit {
+    pub fn fit(&self, spheres: &[Vec4]) -> Vec4 {
+        match self {
+            SphereFit::Enclosing => enclosing_sphere(spheres),
+            SphereFit::VolumePreserving => volume_preserving_sphere(spheres),
+        }
+    }
+}
+
+/// Distance from `center` to the furthest point of `sphere`.
+fn reach(center: &Vec3, sphere: &Vec4) -> f32 {
+    distance(center, &sphere.xyz()) + sphere.w
+}
+
+/// Sphere with the given center and the smallest radius enclosing all spheres.
+fn centered_sphere(center: Vec3, spheres: &[Vec4]) -> Vec4 {
+    let radius = spheres
+        .iter()
+        .map(|s| reach(&center, s))
+        .fold(0.0f32, f32::max);
+
+    vec4(center.x, center.y, center.z, radius)
+}
+
+/// Sphere furthest from `center`, measured to its far side.
+fn furthest(center: &Vec3, spheres: &[Vec4]) -> Vec4 {
+    spheres
+        .iter()
+        .copied()
+        .fold((spheres[0], std::f32::NEG_INFINITY), |acc, s| {
+            let d = reach(center, &s);
+            if d > acc.1 {
+                (s, d)
+            } else {
+                acc
+            }
+        })
+        .0
+}
+
+/// Smallest sphere enclosing `sphere` and `other`.
+fn grow(sphere: Vec4, other: &Vec4) -> Vec4 {
+    let center = sphere.xyz();
+    let d = distance(&center, &other.xyz());
+
+    if d + other.w <= sphere.w {
+        return sphere;
+    }
+    if d + sphere.w <= other.w {
+        return *other;
+    }
+
+    let radius = (sphere.w + d + other.w) * 0.5;
+    let center = center + (other.xyz() - center) * ((radius - sphere.w) / d);
+
+    vec4(center.x, center.y, center.z, radius)
+}
+
+/// Near-minimal sphere enclosing all spheres. No spheres give a zero sphere.
+///
+/// The initial sphere spans the two mutually furthest spheres and is grown over the rest as in Ritter's
+/// algorithm, unless the sphere around the centroid is smaller. Its center is then pulled towards the
+/// furthest sphere with decreasing steps (Bădoiu and Clarkson), which approaches the minimal sphere.
+/// The radius is always measured to the furthest sphere, so all spheres are enclosed regardless of the
+/// number of steps.
+pub fn enclosing_sphere(spheres: &[Vec4]) -> Vec4 {
+    if spheres.is_empty() {
+        return zero();
+    }
+
+    // Ritter's sphere
+    let a = furthest(&spheres[0].xyz(), spheres);
+    let b = furthest(&a.xyz(), spheres);
+    let mut sphere = spheres.iter().fold(grow(a, &b), grow);
+    // Growth is exact only up to rounding
+    sphere = centered_sphere(sphere.xyz(), spheres);
+    let mut center = sphere.xyz();
+
+    // Spheres of uniformly spread clusters are often tighter around the centroid
+    let centroid = spheres.iter().map(|s| s.xyz()).sum::<Vec3>() / spheres.len() as f32;
+    let centroid_sphere = centered_sphere(centroid, spheres);
+    if centroid_sphere.w < sphere.w {
+        sphere = centroid_sphere;
+    }
+
+    for i in 1..=REFINEMENT_ITERATIONS {
+        let s = furthest(&center, spheres);
+        let direction = s.xyz() - center;
+        let length = direction.norm();
+        let far_point = if length > 0.0 {
+            s.xyz() + direction * (s.w / length)
+        } else {
+            s.xyz()
+        };
+
+        center += (far_point - center) / (i + 1) as f32;
+
+        let candidate = centered_sphere(center, spheres);
+        if candidate.w < sphere.w {
+            sphere = candidate;
+        }
+    }
+
+    sphere
+}
+
+/// Sphere at the centroid of the spheres, with the total volume of the spheres.
+pub fn volume_preserving_sphere(spheres: &[Vec4]) -> Vec4 {
+    if spheres.is_empty() {
+        return zero();
+    }
+
+    let center: Vec3 = spheres.iter().map(|s| s.xyz()).sum::<Vec3>() / spheres.len() as f32;
+    let volume: f32 = spheres.iter().map(|s| s.w * s.w * s.w).sum();
+
+    vec4(center.x, center.y, center.z, volume.cbrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec3;
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+
+    fn contains(sphere: &Vec4, other: &Vec4) -> bool {
+        reach(&sphere.xyz(), other) <= sphere.w * (1.0 + 1e-5) + 1e-5
+    }
+
+    #[test]
+    fn enclosing_sphere_contains_all_spheres() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..200 {
+            let count = rng.gen_range(1..100);
+            let spread = rng.gen_range(0.0..50.0);
+            let spheres: Vec<Vec4> = (0..count)
+                .map(|_| {
+                    vec4(
+                        rng.gen_range(-spread..=spread),
+                        rng.gen_range(-spread..=spread),
+                        rng.gen_range(-spread..=spread),
+                        rng.gen_range(0.0..3.0),
+                    )
+                })
+                .collect();
+
+            let sphere = enclosing_sphere(&spheres);
+            assert!(spheres.iter().all(|s| contains(&sphere, s)));
+
+            // Never larger than the sphere around the centroid
+            let centroid = spheres.iter().map(|s| s.xyz()).sum::<Vec3>() / count as f32;
+            assert!(sphere.w <= centered_sphere(centroid, &spheres).w + 1e-4);
+        }
+    }
+
+    #[test]
+    fn enclosing_sphere_of_simple_configurations() {
+        let single = vec4(1.0, 2.0, 3.0, 1.5);
+        assert_eq!(enclosing_sphere(&[single]), single);
+
+        // A sphere inside another one
+        let outer = vec4(0.0, 0.0, 0.0, 5.0);
+        assert_eq!(enclosing_sphere(&[vec4(1.0, 1.0, 0.0, 1.0), outer]), outer);
+
+        // Two spheres are enclosed by the sphere spanning them
+        let pair = enclosing_sphere(&[vec4(-4.0, 0.0, 0.0, 1.0), vec4(4.0, 0.0, 0.0, 1.0)]);
+        assert!(distance(&pair.xyz(), &zero()) < 1e-4);
+        assert!((pair.w - 5.0).abs() < 1e-4);
+
+        // Spheres at the vertices of a regular tetrahedron
+        let tetrahedron = [
+            vec4(1.0, 1.0, 1.0, 0.5),
+            vec4(1.0, -1.0, -1.0, 0.5),
+            vec4(-1.0, 1.0, -1.0, 0.5),
+            vec4(-1.0, -1.0, 1.0, 0.5),
+        ];
+        let sphere = enclosing_sphere(&tetrahedron);
+        assert!(tetrahedron.iter().all(|s| contains(&sphere, s)));
+        assert!(sphere.w < 3.0f32.sqrt() + 0.5 + 0.05);
+    }
+
+    #[test]
+    fn volume_preserving_sphere_keeps_volume() {
+        let spheres = [vec4(0.0, 0.0, 0.0, 1.0), vec4(2.0, 0.0, 0.0, 1.0)];
+        let sphere = volume_preserving_sphere(&spheres);
+
+        assert!(distance(&sphere.xyz(), &vec3(1.0, 0.0, 0.0)) < 1e-6);
+        assert!((sphere.w - 2.0f32.cbrt()).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,12 @@
+//! Fixtures shared by the tests of the reducers.
+use nalgebra_glm::{vec4, Vec4};
+
+/// Helical chain of atoms, roughly the shape and density of a protein backbone.
+pub(crate) fn chain(count: usize) -> Vec<Vec4> {
+    (0..count)
+        .map(|i| {
+            let t = i as f32 * 0.3;
+            vec4(10.0 * t.cos(), 10.0 * t.sin(), t * 0.5, 1.5)
+        })
+        .collect()
+}
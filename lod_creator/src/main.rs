@@ -1,141 +1,217 @@
-use kmeans;
-use nalgebra_glm::*;
-use rpdb::{molecule::Molecule, molecule::MoleculeLod, FromRon, ToRon};
-
-fn sphere_sreen_space_area(projection: Mat4, dimensions: Vec2, center: Vec3, radius: f32) -> f32 {
-    let d2 = dot(&center, &center);
-    let a = (d2 - radius * radius).sqrt();
-
-    // view-aligned "right" vector (right angle to the view plane from the center of the sphere. Since  "up" is always (0,n,0), replaced cross product with vec3(-c.z, 0, c.x)
-    let right = (radius / a) * vec3(-center.z, 0.0, center.x);
-    let up = vec3(0.0, radius, 0.0);
-
-    let projected_right = projection * vec4(right.x, right.y, right.z, 0.0);
-    let projected_up = projection * vec4(up.x, up.y, up.z, 0.0);
+use rpdb::args::{fail, Args};
+use rpdb::binary::{self, ToBinary};
+use rpdb::lod::*;
+use rpdb::molecule::Molecule;
+use rpdb::structure::{molecule_path, Structure};
+use rpdb::{is_molecule_file, FromFile, ToRon};
+
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: lod_creator [options] <molecule, structure or directory> [output]
+
+Replaces the LODs of molecules by simplifications of their first LOD. A structure processes all molecules it
+references, a directory all molecules in it. Files are overwritten unless an output file, or a directory for
+several molecules, is given.
+
+Options:
+  --reducer <name>          kmeans, octree, agglomerative or residue (default kmeans)
+  --seed <u64>              seed of randomized reducers
+  --width <pixels>          viewport width (default 1920)
+  --height <pixels>         viewport height (default 1080)
+  --fov <degrees>           vertical field of view (default 45)
+  --area-threshold <pixels> area of the largest sphere below which a LOD is replaced (default 32)
+  --ratios <r,r,...>        candidate sizes as fractions of the atoms of the first LOD
+  --counts <n,n,...>        candidate sizes as numbers of spheres
+  --step <distance>         linear breakpoint search with the given step instead of bisection
+  --tolerance <distance>    tolerance of the bisection breakpoint search (default 0.01)
+  --lod-error <pixels>      place breakpoints where the error of a LOD projects below this many pixels
+  --dry-run                 only report the LODs that would be produced";
+
+fn lod_config(args: &mut Args) -> LodConfig {
+    let mut config = LodConfig::default();
+
+    if let Some(width) = args.parse_option("--width") {
+        config.width = width;
+    }
+    if let Some(height) = args.parse_option("--height") {
+        config.height = height;
+    }
+    if let Some(fov) = args.parse_option::<f32>("--fov") {
+        config.fov = fov.to_radians();
+    }
+    if let Some(threshold) = args.parse_option("--area-threshold") {
+        config.area_threshold = threshold;
+    }
 
-    let projected_center = projection * vec4(center.x, center.y, center.z, 1.0);
+    match (
+        args.parse_list_option("--ratios"),
+        args.parse_list_option("--counts"),
+    ) {
+        (Some(_), Some(_)) => args.usage_error("--ratios and --counts are exclusive"),
+        (Some(ratios), None) => config.targets = LodTargets::Ratios(ratios),
+        (None, Some(counts)) => config.targets = LodTargets::Counts(counts),
+        (None, None) => {}
+    }
 
-    let mut north = projected_center + projected_up;
-    let mut east = projected_center + projected_right;
-    let mut south = projected_center - projected_up;
-    let mut west = projected_center - projected_right;
+    match (
+        args.parse_option("--step"),
+        args.parse_option("--tolerance"),
+    ) {
+        (Some(_), Some(_)) => args.usage_error("--step and --tolerance are exclusive"),
+        (Some(step), None) => config.search = BreakpointSearch::Linear { step },
+        (None, Some(tolerance)) => config.search = BreakpointSearch::Bisection { tolerance },
+        (None, None) => {}
+    }
 
-    north /= north.w;
-    east /= east.w;
-    west /= west.w;
-    south /= south.w;
+    config
+}
 
-    let north = vec2(north.x, north.y);
-    let east = vec2(east.x, east.y);
-    let west = vec2(west.x, west.y);
-    let south = vec2(south.x, south.y);
+/// Molecule files to process, found from the input path.
+fn molecule_paths(input: &Path) -> Result<Vec<PathBuf>, String> {
+    if input.is_dir() {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(input)
+            .map_err(|e| format!("{}: {}", input.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let extension = path.extension().and_then(|e| e.to_str());
+                extension == Some("ron") || extension == Some(binary::EXTENSION)
+            })
+            .filter(|path| is_molecule_file(path))
+            .collect();
+        paths.sort();
+
+        return Ok(paths);
+    }
 
-    let box_min = min2(&min2(&min2(&east, &west), &north), &south);
-    let box_max = max2(&max2(&max2(&east, &west), &north), &south);
+    if is_molecule_file(input) {
+        return Ok(vec![input.to_path_buf()]);
+    }
 
-    let box_min = box_min * 0.5 + vec2(0.5, 0.5);
-    let box_max = box_max * 0.5 + vec2(0.5, 0.5);
+    let structure = Structure::try_from_file(input).map_err(|e| e.to_string())?;
+    let mut names: Vec<String> = structure.flatten().keys().cloned().collect();
+    names.sort();
 
-    let area = box_max - box_min;
-    let area = area.component_mul(&dimensions);
-    let area = area.x * area.y;
+    Ok(names
+        .iter()
+        .map(|name| molecule_path(input, name))
+        .collect())
+}
 
-    area
+fn print_lods(molecule: &Molecule) {
+    for (index, lod) in molecule.lods().iter().enumerate() {
+        print!(
+            "  LOD {}: {} atoms, breakpoint {}, max radius {}",
+            index,
+            lod.atoms().len(),
+            lod.breakpoint(),
+            lod.max_radius()
+        );
+        match lod.error() {
+            Some(error) => println!(", error {}", error),
+            None => println!(),
+        }
+    }
 }
 
 fn main() {
-    // Load existing molecule
-    let args: Vec<String> = std::env::args().collect();
-    let mut molecule = Molecule::from_ron(&args[1]);
-
-    // Create new LODs
-    let mut lods = Vec::new();
-    lods.push(molecule.lods()[0].clone());
-
-    // Constants
-    let width = 1920.0;
-    let height = 1080.0;
-    let aspect = width / height;
-    let projection = infinite_perspective_rh_no(aspect, 0.785398163, 0.1);
-    let ratios = [0.9, 0.75, 0.5, 0.25, 0.1,
-                  0.075, 0.05, 0.025, 0.01,
-                  0.005, 0.001, 0.0005, 0.0001
-    ];
-    let area_threshold = 32.0;
-
-    // Current largest radius that is being projected
-    let mut radius = lods.last().unwrap().max_radius();
-    let mut z = radius * 2.0;
-
-    // Iteratively zoom out
-    let mut current_ratio_index = 0;
-    'main: loop {
-        z += 1.0;
-
-        if current_ratio_index >= ratios.len() - 1 {
-            break 'main;
+    let mut args = Args::new(USAGE);
+
+    let dry_run = args.flag("--dry-run");
+
+    // Simplification of molecules into LODs
+    let seed = args.parse_option::<u64>("--seed");
+    let reducer = kmeans::reducer_from_name(
+        &args
+            .option("--reducer")
+            .unwrap_or_else(|| "kmeans".to_string()),
+        seed,
+    )
+    .unwrap_or_else(|e| args.usage_error(&e));
+
+    let mut config = lod_config(&mut args);
+    let lod_error = args.parse_option::<f32>("--lod-error");
+    if let Some(pixels) = lod_error {
+        config.error_threshold = pixels;
+    }
+
+    let positional = args.positional();
+    if positional.is_empty() || positional.len() > 2 {
+        args.usage_error("expected an input and an optional output path");
+    }
+
+    let input = Path::new(&positional[0]);
+    let output = positional.get(1).map(PathBuf::from);
+
+    let paths = molecule_paths(input).unwrap_or_else(|e| fail(e));
+
+    // A single molecule may be written to a new file, several ones only to a directory
+    let output_is_directory = output
+        .as_ref()
+        .map_or(false, |output| output.is_dir() || paths.len() > 1);
+    if let Some(output) = &output {
+        let directory = if output_is_directory {
+            Some(output.as_path())
+        } else {
+            output.parent()
+        };
+
+        if let (Some(directory), false) = (directory, dry_run) {
+            std::fs::create_dir_all(directory)
+                .unwrap_or_else(|e| fail(format!("{}: {}", directory.display(), e)));
         }
+    }
 
-        let view = look_at(
-            &vec3(0.0, 0.0, z as f32),
-            &vec3(0.0, 0.0, 0.0),
-            &vec3(0.0, 1.0, 0.0),
+    let mut failed = false;
+    for path in paths.iter() {
+        let mut molecule = match Molecule::try_from_file(path) {
+            Ok(molecule) => molecule,
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
+                continue;
+            }
+        };
+
+        println!(
+            "{} ({} atoms)",
+            path.display(),
+            molecule.lods()[0].atoms().len()
         );
-        let position = view * vec4(0.0, 0.0, 0.0, 1.0);
-        let area = sphere_sreen_space_area(projection, vec2(width, height), position.xyz(), radius);
 
-        if !area.is_finite() {
+        if lod_error.is_some() {
+            molecule.create_lods_by_error(reducer.as_ref(), &config);
+        } else {
+            molecule.create_lods(reducer.as_ref(), &config);
+        }
+        print_lods(&molecule);
+
+        if dry_run {
             continue;
         }
 
-        // Breakpoint at area limit
-        // Currently 64 = 8x8 pixel area
-        if area < area_threshold {
-            // println!("{:?}", area);
-            println!("Distance: {}", z);
-
-            // Continue along the reduction ratios
-            for reduction_ratio in current_ratio_index..ratios.len() {                
-                let mut new_centroids_num = (lods[0].atoms().len() as f32 * ratios[reduction_ratio]) as usize;
-
-                // End if It is not possible to reduce further
-                if lods.last().unwrap().atoms().len() == 1 {
-                    break 'main;
-                }
-
-                if new_centroids_num < 1 {
-                    new_centroids_num = 1;
-                }
-
-                let new_means = kmeans::reduce(
-                    lods[0].atoms(),
-                    new_centroids_num,
-                );
-                
-                if new_means.len() == 0 {
-                    continue;
-                }
-
-                let mut new_lod = MoleculeLod::new(new_means, 0.0);
-
-                let new_area = sphere_sreen_space_area(projection, vec2(width, height), position.xyz(), new_lod.max_radius());
-                
-                println!("Current ratio: {}. New centroids: {}. New means real len: {}. New area: {}", ratios[reduction_ratio], new_centroids_num, new_lod.atoms().len(), new_area);
-
-                // If the new are is now above the area limit, save It and continue
-                if new_area > area_threshold || reduction_ratio == ratios.len() - 1{
-                    radius = new_lod.max_radius();
-                    
-                    new_lod.set_breakpoint(z);
-                    lods.push(new_lod);
-                                
-                    current_ratio_index = reduction_ratio + 1;
-                    break;
-                }
+        let out_path = match &output {
+            Some(output) if output_is_directory => output.join(path.file_name().unwrap()),
+            Some(output) => output.clone(),
+            None => path.clone(),
+        };
+
+        // Molecules keep the format they were read in
+        let binary = binary::is_binary_file(path).unwrap_or(false);
+        let written = if binary {
+            molecule.try_to_binary(&out_path)
+        } else {
+            molecule.try_to_ron(&out_path)
+        };
+        match written {
+            Ok(()) => println!("  Written to {}", out_path.display()),
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
             }
-        }        
+        }
     }
 
-    molecule.lods = lods;
-    molecule.to_ron(&args[1]);
+    if failed {
+        std::process::exit(1);
+    }
 }
@@ -6,6 +6,7 @@ use master_thesis::postprocess::*;
 use master_thesis::pvs::*;
 use master_thesis::ssao;
 use master_thesis::structure::*;
+use rpdb::args::Args;
 
 use bytemuck::cast_slice;
 use bytemuck::{Pod, Zeroable};
@@ -19,6 +20,13 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+const USAGE: &str = "Usage: occlusion [options] <structure>...
+
+Renders structures with occlusion culling by their potentially visible sets, cached next to each structure.
+
+Options:
+  --exempt <molecule,...>     molecules whose gaps are never merged, as given to pvs_precompute";
+
 struct ApplicationState {
     pub draw_lod: bool,
     pub draw_occluded: bool,
@@ -176,11 +184,16 @@ impl framework::ApplicationStructure for Application {
         camera.set_pitch(1.5207963267948965);
 
         // Data
-        let args: Vec<String> = std::env::args().collect();
+        let mut args = Args::new(USAGE);
+        let exempt: Vec<String> = args.parse_list_option("--exempt").unwrap_or_default();
+        let paths = args.positional();
+        if paths.is_empty() {
+            args.usage_error("expected at least one structure");
+        }
 
         let mut structures = Vec::new();
         let mut structures_bgs = Vec::new();
-        for path in args[1..].iter() {
+        for path in paths.iter() {
             let (structure, structure_bgs) =
                 Structure::from_ron_with_bgs(&device, path, &per_molecule_bind_group_layout);
 
@@ -311,14 +324,40 @@ impl framework::ApplicationStructure for Application {
                     &device,
                     &camera_bind_group_layout,
                     structure.clone(),
-                    24,
+                    DEFAULT_VIEWS,
+                    &DEFAULT_SHELLS,
                     32,
                 )
             })
             .collect();
 
-        for i in 0..structures_pvs.len() {
-            futures::executor::block_on(structures_pvs[i].compute_all(device, queue));
+        // Sets are cached next to the structures, only those missing from the cache are computed
+        for (structure_pvs, path) in structures_pvs.iter_mut().zip(paths.iter()) {
+            // Set before loading the cache keyed by the exempt molecules
+            structure_pvs.set_exempt(exempt.clone());
+
+            let cache_path = master_thesis::pvs_cache::cache_path(path);
+            match structure_pvs.load_cache(&cache_path) {
+                Ok(true) => println!("Loaded PVS cache: {}", cache_path.display()),
+                Ok(false) => {}
+                Err(e) => println!("Ignoring PVS cache {}: {}", cache_path.display(), e),
+            }
+
+            let cached = structure_pvs.computed_count();
+            futures::executor::block_on(structure_pvs.compute_all(device, queue));
+            println!(
+                "PVS of {}: {} sets in {} shells, {} KiB",
+                path,
+                structure_pvs.sets.len(),
+                structure_pvs.shells().len(),
+                structure_pvs.footprint() / 1024
+            );
+
+            if structure_pvs.computed_count() > cached {
+                if let Err(e) = structure_pvs.save_cache(&cache_path) {
+                    println!("Could not write PVS cache {}: {}", cache_path.display(), e);
+                }
+            }
         }
 
         let n = 11;
@@ -901,7 +940,7 @@ impl framework::ApplicationStructure for Application {
             let rotation = self.structures_transforms[i].2.fixed_slice::<U3, U3>(0, 0);
             let position = self.structures_transforms[i].1.column(3).xyz();
 
-            let direction = rotation.try_inverse().unwrap() * normalize(&(eye - position));
+            let direction = rotation.try_inverse().unwrap() * (eye - position);
 
             // let start = Instant::now();
             if futures::executor::block_on(
@@ -986,7 +1025,7 @@ impl framework::ApplicationStructure for Application {
                 let position = self.structures_transforms[i].1.column(3).xyz();
 
                 let direction = eye - position;
-                let direction_norm_rot = rotation.try_inverse().unwrap() * normalize(&direction);
+                let direction_rot = rotation.try_inverse().unwrap() * direction;
 
                 let distance = (direction.magnitude() - 2.0 * structure.bounding_radius()).max(1.0);
 
@@ -1074,7 +1113,7 @@ impl framework::ApplicationStructure for Application {
 
                     // IF !draw_occluded && PVS is available -> iterate only over visible parts
                     if !draw_occluded {
-                        if let Some(pvs) = structure_pvs.get_from_eye(direction_norm_rot) {
+                        if let Some(pvs) = structure_pvs.get_from_eye(direction_rot) {
                             for range in pvs.visible[molecule_id].iter() {
                                 rpass.draw(start..end, range.0..range.1);
                             }
@@ -321,7 +321,8 @@ impl framework::ApplicationStructure for Application {
             &device,
             &camera_bind_group_layout,
             structure.clone(),
-            24,
+            DEFAULT_VIEWS,
+            &DEFAULT_SHELLS,
             reduce,
         );
         futures::executor::block_on(structure_pvs.compute_all(&device, &queue));
@@ -836,7 +837,7 @@ impl framework::ApplicationStructure for Application {
         let time = time.as_secs_f32() + time.subsec_millis() as f32;
 
         if self.reduce != self.structure_pvs.ranges_limit() {            
-            self.structure_pvs = self.pvs_module.pvs_field(device, &self.camera_bind_group_layout, self.structure.clone(), 24, self.reduce);
+            self.structure_pvs = self.pvs_module.pvs_field(device, &self.camera_bind_group_layout, self.structure.clone(), DEFAULT_VIEWS, &DEFAULT_SHELLS, self.reduce);
             futures::executor::block_on(self.structure_pvs.compute_all(device, queue));
         }
 
@@ -875,7 +876,7 @@ impl framework::ApplicationStructure for Application {
         let rotation = self.structure_transforms.2.fixed_slice::<U3, U3>(0, 0);
         let position = self.structure_transforms.1.column(3).xyz();
 
-        let direction = rotation.try_inverse().unwrap() * normalize(&(eye - position));
+        let direction = rotation.try_inverse().unwrap() * (eye - position);
 
         if futures::executor::block_on(self.structure_pvs.compute_from_eye(
             device,
@@ -948,7 +949,7 @@ impl framework::ApplicationStructure for Application {
             let position = self.structure_transforms.1.column(3).xyz();
 
             let direction = eye - position;
-            let direction_norm_rot = rotation.try_inverse().unwrap() * normalize(&direction);
+            let direction_rot = rotation.try_inverse().unwrap() * direction;
 
             let distance = (direction.magnitude() - 2.0 * structure.bounding_radius()).max(1.0);
 
@@ -1008,7 +1009,7 @@ impl framework::ApplicationStructure for Application {
 
                 // IF !draw_occluded && PVS is available -> iterate only over visible parts
                 if !draw_occluded {
-                    if let Some(pvs) = structure_pvs.get_from_eye(-direction_norm_rot) {
+                    if let Some(pvs) = structure_pvs.get_from_eye(-direction_rot) {
                         for range in pvs.visible[molecule_id].iter() {
                             rpass.draw(start..end, range.0..range.1);
                         }
@@ -0,0 +1,170 @@
+use master_thesis::camera::*;
+use master_thesis::pvs::*;
+use master_thesis::pvs_cache::cache_path;
+use master_thesis::structure::*;
+use rpdb::args::{fail, Args};
+
+use wgpu::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+const USAGE: &str = "Usage: pvs_precompute [options] <structure>...
+
+Computes the potentially visible sets of structures and caches them next to each structure.
+
+Options:
+  --views <count>             views sampled around the structure (default 256)
+  --shells <r,r,...>          distances of the views in bounding radii greater than 1, inf for orthographic
+                              views (default 1.5,3,inf)
+  --ranges-limit <count>      ranges of a molecule above which its gaps are merged (default 32)
+  --exempt <molecule,...>     molecules whose gaps are never merged, as given to the viewer
+  --force                     recompute sets present in the cache";
+
+async fn run(
+    paths: &[String],
+    views_count: usize,
+    shells: &[f32],
+    ranges_limit: usize,
+    exempt: &[String],
+    force: bool,
+) {
+    let instance = Instance::new(BackendBit::VULKAN);
+    let adapter = instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: None,
+        })
+        .await
+        .expect("No suitable GPU adapter.");
+
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                label: None,
+                features: Features::PUSH_CONSTANTS,
+                limits: Limits {
+                    max_push_constant_size: 32,
+                    ..Limits::default()
+                },
+            },
+            None,
+        )
+        .await
+        .expect("Could not create a device.");
+
+    // Same layouts as in the viewer
+    let camera_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Camera bind group layout"),
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStage::all(),
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(CameraUbo::size()),
+            },
+            count: None,
+        }],
+    });
+
+    let molecule_entry = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStage::all(),
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let per_molecule_bind_group_layout =
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Molecule bind group layout"),
+            entries: &[molecule_entry(0), molecule_entry(1)],
+        });
+
+    let pvs_module = Rc::new(StructurePvsModule::new(
+        &device,
+        &camera_bind_group_layout,
+        &per_molecule_bind_group_layout,
+    ));
+
+    for path in paths.iter() {
+        let structure = Structure::from_ron(&device, path, &per_molecule_bind_group_layout);
+        let mut structure_pvs = pvs_module.pvs_field(
+            &device,
+            &camera_bind_group_layout,
+            Rc::new(RefCell::new(structure)),
+            views_count,
+            shells,
+            ranges_limit,
+        );
+        structure_pvs.set_exempt(exempt.to_vec());
+
+        let cache_path = cache_path(path);
+        if !force {
+            match structure_pvs.load_cache(&cache_path) {
+                Ok(true) => println!("{}: {} views cached", path, structure_pvs.computed_count()),
+                Ok(false) => {}
+                Err(e) => println!("{}: ignoring the cache, {}", path, e),
+            }
+        }
+
+        let start = Instant::now();
+        let cached = structure_pvs.computed_count();
+        structure_pvs.compute_all(&device, &queue).await;
+        println!(
+            "{}: computed {} of {} views in {:.1?}",
+            path,
+            structure_pvs.computed_count() - cached,
+            structure_pvs.sets.len(),
+            start.elapsed()
+        );
+
+        match structure_pvs.save_cache(&cache_path) {
+            Ok(()) => println!(
+                "Writing PVS cache to: {}, {} shells, {} KiB of ranges, {} KiB on disk",
+                cache_path.display(),
+                structure_pvs.shells().len(),
+                structure_pvs.footprint() / 1024,
+                std::fs::metadata(&cache_path).map_or(0, |metadata| metadata.len()) / 1024
+            ),
+            Err(e) => fail(format!("Could not write {}: {}", cache_path.display(), e)),
+        }
+    }
+}
+
+fn main() {
+    let mut args = Args::new(USAGE);
+
+    let views_count = args.parse_option("--views").unwrap_or(DEFAULT_VIEWS);
+    let shells = args
+        .parse_list_option("--shells")
+        .unwrap_or_else(|| DEFAULT_SHELLS.to_vec());
+    let ranges_limit = args.parse_option("--ranges-limit").unwrap_or(32);
+    // The viewer only uses the cache when given the same exempt molecules
+    let exempt: Vec<String> = args.parse_list_option("--exempt").unwrap_or_default();
+    let force = args.flag("--force");
+
+    if views_count == 0 {
+        args.usage_error("--views must be positive");
+    }
+    if shells.is_empty() || shells.iter().any(|shell| *shell <= 1.0) {
+        args.usage_error("--shells must be greater than 1");
+    }
+    let paths = args.positional();
+    if paths.is_empty() {
+        args.usage_error("expected at least one structure");
+    }
+
+    futures::executor::block_on(run(
+        &paths,
+        views_count,
+        &shells,
+        ranges_limit,
+        &exempt,
+        force,
+    ));
+}
@@ -5,6 +5,8 @@ pub mod hilbert;
 pub mod pipelines;
 pub mod postprocess;
 pub mod pvs;
+pub mod pvs_cache;
+pub mod pvs_cpu;
 pub mod ssao;
 pub mod structure;
 
@@ -14,13 +14,46 @@ use wgpu::*;
 use std::cell::RefCell;
 use std::convert::TryInto;
 use std::mem::size_of;
+use std::path::Path;
 use std::rc::Rc;
 
 use crate::camera::*;
 use crate::pipelines::SphereBillboardsDepthPipeline;
+use crate::pvs_cache::PvsCache;
+use crate::pvs_cpu::CpuStructure;
 use crate::structure::*;
 use crate::*;
 
+/// Width and height of the depth buffer views are rendered into.
+pub const DEPTH_RESOLUTION: u32 = 512;
+
+/// Number of views of a field, unless chosen otherwise.
+pub const DEFAULT_VIEWS: usize = 256;
+
+/// Distances of shells of a field, unless chosen otherwise. In bounding radii of the structure.
+pub const DEFAULT_SHELLS: [f32; 3] = [1.5, 3.0, std::f32::INFINITY];
+
+/// Projection and distance of the camera rendering the views of the shell at `shell` bounding radii.
+///
+/// Views of finite shells are rendered in perspective from the shell with reversed depth, so that the greater depth
+/// kept by the depth test is the closer one. The infinitely far shell is rendered orthographically from the opposite
+/// side of the structure, where the greater depth is the one closer to the viewer.
+pub fn shell_camera(bounding_radius: f32, shell: f32) -> (Mat4, f32) {
+    let r = bounding_radius;
+
+    if shell.is_infinite() {
+        (ortho_rh_zo(-r, r, -r, r, 0.0, 2.0 * r), -r)
+    } else {
+        let distance = shell * r;
+        let fovy = 2.0 * (1.0 / shell).asin();
+
+        (
+            reversed_perspective_rh_zo(1.0, fovy, distance - r, distance + r),
+            distance,
+        )
+    }
+}
+
 pub struct StructurePvsModule {
     ///
     depth: TextureView,
@@ -44,8 +77,8 @@ impl StructurePvsModule {
             .create_texture(&TextureDescriptor {
                 label: None,
                 size: Extent3d {
-                    width: 512,
-                    height: 512,
+                    width: DEPTH_RESOLUTION,
+                    height: DEPTH_RESOLUTION,
                     depth_or_array_layers: 1,
                 },
                 mip_level_count: 1,
@@ -103,15 +136,29 @@ impl StructurePvsModule {
         device: &Device,
         camera_bind_group_layout: &BindGroupLayout,
         structure: Rc<RefCell<Structure>>,
-        step: u32,
+        views_count: usize,
+        shells: &[f32],
         ranges_limit: usize,
     ) -> StructurePvsField {
-        let views_per_circle = 360 / step;
-        let sets = vec![None; (views_per_circle * views_per_circle) as usize];
+        assert!(!shells.is_empty(), "a field needs at least one shell");
+        assert!(
+            shells.iter().all(|shell| *shell > 1.0),
+            "shells must lie outside of the structure"
+        );
+
+        let mut shells = shells.to_vec();
+        shells.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let views: Vec<TVec3<f64>> = rpdb::lod::fibonacci_directions(views_count)
+            .iter()
+            .map(|direction| direction.map(f64::from))
+            .collect();
+        let sets = vec![None; views.len() * shells.len()];
 
         let r = structure.borrow().bounding_radius();
-        let projection = ortho_rh_zo(-r, r, -r, r, 0.0, 2.0 * r);
-        let camera = RotationCamera::new(device, camera_bind_group_layout, &projection, -r, 0.0);
+        let (projection, distance) = shell_camera(r, shells[0]);
+        let camera =
+            RotationCamera::new(device, camera_bind_group_layout, &projection, distance, 0.0);
 
         let mut visible = Vec::new();
         let mut visible_staging = Vec::new();
@@ -161,8 +208,10 @@ impl StructurePvsModule {
 
             sets,
 
-            step,
+            views,
+            shells,
             ranges_limit,
+            exempt: Vec::new(),
 
             visible,
             visible_staging,
@@ -187,12 +236,19 @@ pub struct StructurePvsField {
     ///
     pub sets: Vec<Option<StructurePvs>>,
 
-    /// Step of distribution of discretized views. In degrees.
-    step: u32,
+    /// Directions of the views, distributed uniformly on a unit sphere.
+    views: Vec<TVec3<f64>>,
+
+    /// Distances of the shells the views are computed at, in bounding radii of the structure, ascending. Sets are
+    /// stored by shells, each with all views.
+    shells: Vec<f32>,
 
     /// Upper bound of ranges to generate.
     ranges_limit: usize,
 
+    /// Names of the molecules whose gaps are never merged.
+    exempt: Vec<String>,
+
     ///
     visible: Vec<Buffer>,
 
@@ -207,54 +263,68 @@ pub struct StructurePvsField {
 }
 
 impl StructurePvsField {
-    pub fn spherical_to_snapped(&self, spherical_coords: TVec2<f64>) -> TVec2<u32> {
-        // Convert spherical coordinates to degrees and modulo them into the range of 0-360.
-        let snapped_coords =
-            spherical_coords.apply_into(|e| ((e.to_degrees().round() % 360.0) + 360.0) % 360.0);
-
-        // Snap spherical coordinates to the closest view given by step size.
-        let snapped_coords = TVec2::new(
-            (((snapped_coords[0] as u32 + self.step - 1) / self.step) * self.step) % 360,
-            (((snapped_coords[1] as u32 + self.step - 1) / self.step) * self.step) % 360,
-        );
-
-        snapped_coords
+    pub fn views(&self) -> &[TVec3<f64>] {
+        &self.views
     }
 
-    pub fn spherical_to_index(&self, spherical_coords: TVec2<f64>) -> usize {
-        self.snapped_to_index(self.spherical_to_snapped(spherical_coords))
+    pub fn shells(&self) -> &[f32] {
+        &self.shells
     }
 
-    pub fn snapped_to_spherical(&self, snapped_coords: TVec2<u32>) -> TVec2<f64> {
-        // Conver to floating point radians.
-        let spherical_coords = vec2(
-            (snapped_coords.x as f64).to_radians(),
-            (snapped_coords.y as f64).to_radians(),
-        );
+    /// Index of the set of the view `view` in the shell `shell`.
+    pub fn index(&self, shell: usize, view: usize) -> usize {
+        shell * self.views.len() + view
+    }
 
-        spherical_coords
+    /// Shell and view of the set at `index`.
+    pub fn index_to_shell_view(&self, index: usize) -> (usize, usize) {
+        (index / self.views.len(), index % self.views.len())
     }
 
-    pub fn snapped_to_index(&self, snapped_coords: TVec2<u32>) -> usize {
-        debug_assert!(snapped_coords.x < 360);
-        debug_assert!(snapped_coords.y < 360);
-        debug_assert!(snapped_coords.x % self.step == 0);
-        debug_assert!(snapped_coords.y % self.step == 0);
+    /// Index of the closest shell at or beyond the distance of `eye` from the center of the structure, or of the
+    /// farthest shell.
+    pub fn nearest_shell(&self, eye: &TVec3<f64>) -> usize {
+        let distance = length(eye) as f32 / self.structure.borrow().bounding_radius();
 
-        let steps = 360 / self.step;
+        self.shells
+            .iter()
+            .position(|shell| *shell >= distance)
+            .unwrap_or(self.shells.len() - 1)
+    }
 
-        ((snapped_coords.x / self.step) * steps + (snapped_coords.y / self.step)) as usize
+    /// Index of the set of the view closest to `eye`, in its shell.
+    pub fn nearest_index(&self, eye: &TVec3<f64>) -> usize {
+        self.index(self.nearest_shell(eye), self.nearest_view(eye))
     }
 
-    pub fn index_to_snapped(&self, index: usize) -> TVec2<u32> {
-        let index = index as u32;
-        let steps = 360 / self.step;
+    /// Index of the view closest to the direction of `eye`.
+    pub fn nearest_view(&self, eye: &TVec3<f64>) -> usize {
+        self.nearest_views(eye, 1)[0]
+    }
+
+    /// Indices of the `k` views closest to the direction of `eye`, from the closest one.
+    pub fn nearest_views(&self, eye: &TVec3<f64>, k: usize) -> Vec<usize> {
+        let eye = normalize(eye);
+
+        let mut indices: Vec<usize> = (0..self.views.len()).collect();
+        indices.sort_by(|a, b| {
+            let a = dot(&self.views[*a], &eye);
+            let b = dot(&self.views[*b], &eye);
 
-        vec2((index / steps) * self.step, (index % steps) * self.step)
+            b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        indices.truncate(k.max(1));
+
+        indices
     }
 
     pub fn index_to_spherical(&self, index: usize) -> TVec2<f64> {
-        self.snapped_to_spherical(self.index_to_snapped(index))
+        cartesian_to_spherical(&self.views[self.index_to_shell_view(index).1])
+    }
+
+    /// Distance of the shell of the set at `index`, in bounding radii of the structure.
+    pub fn index_to_shell(&self, index: usize) -> f32 {
+        self.shells[self.index_to_shell_view(index).0]
     }
 
     pub async fn compute(&mut self, device: &Device, queue: &Queue, index: usize) -> bool {
@@ -268,6 +338,10 @@ impl StructurePvsField {
 
             // Configure camera
             let spherical_coords = self.index_to_spherical(index);
+            let (projection, distance) =
+                shell_camera(structure.bounding_radius(), self.index_to_shell(index));
+            self.camera.set_projection(&projection);
+            self.camera.set_distance(distance);
             self.camera.set_yaw(spherical_coords.x);
             self.camera.set_pitch(spherical_coords.y);
 
@@ -410,25 +484,52 @@ impl StructurePvsField {
         return true;
     }
 
+    /// Computes the potentially visible set from the view `index` like `compute`, but on the CPU from `structure`,
+    /// which must be loaded from the same file as the structure of the field.
+    pub fn compute_cpu(&mut self, structure: &CpuStructure, index: usize) -> bool {
+        if self.sets[index].is_some() {
+            return false;
+        }
+
+        debug_assert_eq!(
+            structure.molecules().len(),
+            self.structure.borrow().molecules().len()
+        );
+
+        let spherical_coords = self.index_to_spherical(index);
+        self.sets[index] = Some(structure.pvs(spherical_coords, self.index_to_shell(index)));
+        self.reduce(index);
+
+        true
+    }
+
+    /// Computes potentially visible sets from all views on the CPU.
+    pub fn compute_all_cpu(&mut self, structure: &CpuStructure) {
+        for index in 0..self.sets.len() {
+            self.compute_cpu(structure, index);
+        }
+    }
+
+    /// Computes the set of the view closest to `eye`, given as in `get_from_eye`.
     pub async fn compute_from_eye(
         &mut self,
         device: &Device,
         queue: &Queue,
         eye: TVec3<f64>,
     ) -> bool {
-        let index = self.spherical_to_index(cartesian_to_spherical(&vec3(eye.x, eye.y, eye.z)));
+        let index = self.nearest_index(&eye);
 
         self.compute(device, queue, index).await
     }
 
-    /// Computes potentially visible sets from all possible polar coordinates given by `step`.
+    /// Computes potentially visible sets from all views.
     pub async fn compute_all(&mut self, device: &Device, queue: &Queue) {
         for index in 0..self.sets.len() {
             self.compute(device, queue, index).await;
         }
     }
 
-    /// Returns potentially visible set from the given viewpoint, given by spherical coordinates in degrees of `step` multiple.
+    /// Returns potentially visible set from the view `index`.
     pub fn get(&self, index: usize) -> Option<&StructurePvs> {
         self.sets[index].as_ref()
     }
@@ -438,95 +539,172 @@ impl StructurePvsField {
     /// # Arguments
     ///
     /// * `eye` - vector **to** viewing point. Must be in the local coordinate system of the structure. If the structure is rotated, so must be the vector.
+    ///   Its length, the distance of the viewing point from the center of the structure, selects the shell.
     ///
     pub fn get_from_eye(&self, eye: Vec3) -> Option<&StructurePvs> {
-        let index = self.spherical_to_index(cartesian_to_spherical(&vec3(
-            eye.x as f64,
-            eye.y as f64,
-            eye.z as f64,
-        )));
+        let index = self.nearest_index(&vec3(eye.x as f64, eye.y as f64, eye.z as f64));
 
         self.get(index)
     }
 
+    /// Returns the union of potentially visible sets of the `k` views closest to the given viewpoint, which is
+    /// conservative for viewpoints between them. `None` until all of them are computed.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - vector **to** viewing point, as in `get_from_eye`.
+    /// * `k` - number of views to merge.
+    ///
+    pub fn get_merged_from_eye(&self, eye: Vec3, k: usize) -> Option<StructurePvs> {
+        let eye = vec3(eye.x as f64, eye.y as f64, eye.z as f64);
+        let shell = self.nearest_shell(&eye);
+
+        let mut sets = Vec::with_capacity(k);
+        for view in self.nearest_views(&eye, k) {
+            sets.push(self.get(self.index(shell, view))?);
+        }
+
+        let visible = (0..sets[0].visible.len())
+            .map(|molecule_id| {
+                let mut ranges: Vec<(u32, u32)> = sets
+                    .iter()
+                    .flat_map(|set| set.visible[molecule_id].iter().cloned())
+                    .collect();
+                ranges.sort();
+
+                compress_ranges(ranges, 0)
+            })
+            .collect();
+
+        Some(StructurePvs { visible })
+    }
+
+    /// Merges the cheapest gaps between ranges of the set at `index` until it has at most `ranges_limit` ranges,
+    /// see `reduce_ranges`.
     pub fn reduce(&mut self, index: usize) {
         let structure = self.structure.borrow();
+
+        let costs: Vec<GapCost> = structure
+            .molecules()
+            .iter()
+            .enumerate()
+            .map(|(molecule_id, molecule)| GapCost {
+                atoms: (molecule.lods()[0].1.end - molecule.lods()[0].1.start) / 3,
+                boundaries: structure
+                    .transforms_sides()
+                    .map_or(Vec::new(), |sides| sides[molecule_id].to_vec()),
+                exempt: self.exempt.iter().any(|name| name == molecule.name()),
+            })
+            .collect();
+
         let pvs = self.sets[index].as_mut().unwrap();
+        pvs.visible = reduce_ranges(&pvs.visible, &costs, self.ranges_limit);
+    }
 
-        let mut gaps = vec![Vec::new(); pvs.visible.len()];
-
-        // Find the gaps
-        for (molecule_index, ranges) in pvs.visible.iter().enumerate() {
-            'ranges: for range_index in 1..ranges.len() {
-                let distance = ranges[range_index].0 - ranges[range_index - 1].1;
-
-                // If we find a gap
-                if distance > 0 && structure.molecules()[molecule_index].name() != "S" {
-                    // Check that it doesn't cross faces
-                    // - potentially large range
-                    for face in structure.transforms_sides().unwrap()[molecule_index].iter() {
-                        if *face >= ranges[range_index - 1].1 && *face <= ranges[range_index].0 {
-                            continue 'ranges;
-                        }
-                    }
-                    gaps[molecule_index].push((ranges[range_index - 1].1, ranges[range_index].0));
-                }
-            }
+    /// Names of the molecules whose gaps are never merged by `reduce`.
+    pub fn exempt(&self) -> &[String] {
+        &self.exempt
+    }
 
-            // Sort the gaps by their length in decreasing order
-            gaps[molecule_index].sort_by(|a, b| {
-                let a_distance = a.1 - a.0;
-                let b_distance = b.1 - b.1;
+    /// Sets the names of the molecules whose gaps are never merged by `reduce`, in any order and ignoring empty
+    /// ones. Sets computed with different ones are dropped.
+    pub fn set_exempt(&mut self, mut exempt: Vec<String>) {
+        exempt.retain(|name| !name.is_empty());
+        exempt.sort();
+        exempt.dedup();
 
-                b_distance.cmp(&a_distance)
-            });
+        if exempt != self.exempt {
+            self.exempt = exempt;
+            self.sets.iter_mut().for_each(|set| *set = None);
         }
+    }
 
-        // Compute how many ranges we have in total across all molecule types
-        let mut ranges_num: usize = pvs.visible.iter().map(|v| v.len()).sum();
-
-        // While we are not under the imposed limit
-        while ranges_num > self.ranges_limit {
-            // Run through gaps of each molecule and find the gap with smallest cost (distance * number of atoms)
-            let mut min_gap = None;
-            let mut min_cost = u32::MAX;
-            let mut min_index = 0;
-            for (molecule_index, gaps) in gaps.iter().enumerate() {
-                if let Some(gap) = gaps.last() {
-                    let atoms_count = (structure.molecules()[molecule_index].lods()[0].1.end
-                        - structure.molecules()[molecule_index].lods()[0].1.start)
-                        / 3;
-                    let distance = gap.1 - gap.0;
-
-                    let cost = distance * atoms_count;
-
-                    if cost < min_cost {
-                        min_gap = Some(gap);
-                        min_cost = cost;
-                        min_index = molecule_index;
-                    }
-                }
-            }
+    pub fn ranges_limit(&self) -> usize {
+        self.ranges_limit
+    }
 
-            // Add the found smallest gap to the list of ranges
-            if let Some(gap) = min_gap {
-                pvs.visible[min_index].push(*gap);
-                gaps[min_index].pop();
-                ranges_num -= 1;
-            } else {
-                break;
+    /// Size in bytes of the ranges of all computed sets.
+    pub fn footprint(&self) -> usize {
+        self.sets
+            .iter()
+            .flatten()
+            .flat_map(|set| set.visible.iter())
+            .map(|ranges| ranges.len() * size_of::<(u32, u32)>())
+            .sum()
+    }
+
+    /// Number of views whose potentially visible sets are already computed.
+    pub fn computed_count(&self) -> usize {
+        self.sets.iter().filter(|set| set.is_some()).count()
+    }
+
+    /// Potentially visible sets computed so far, to be stored in a cache file.
+    pub fn to_cache(&self) -> PvsCache {
+        let structure = self.structure.borrow();
+
+        PvsCache {
+            hash: structure.transforms_hash(),
+            views_count: self.views.len(),
+            shells: self.shells.clone(),
+            exempt: self.exempt.clone(),
+            ranges_limit: self.ranges_limit,
+            molecules: structure
+                .molecules()
+                .iter()
+                .map(|molecule| molecule.name().to_string())
+                .collect(),
+            sets: self
+                .sets
+                .iter()
+                .map(|set| set.as_ref().map(|pvs| pvs.visible.clone()))
+                .collect(),
+        }
+    }
+
+    /// Takes the sets of `cache` which are not computed yet, if the cache was created for the same structure,
+    /// views, shells, ranges limit and exempt molecules. Returns whether the cache was used.
+    pub fn apply_cache(&mut self, cache: PvsCache) -> bool {
+        let matches = {
+            let structure = self.structure.borrow();
+
+            cache.hash == structure.transforms_hash()
+                && cache.views_count == self.views.len()
+                && cache.shells == self.shells
+                && cache.exempt == self.exempt
+                && cache.ranges_limit == self.ranges_limit
+                && cache.sets.len() == self.sets.len()
+                && cache.molecules.len() == structure.molecules().len()
+                && cache
+                    .molecules
+                    .iter()
+                    .zip(structure.molecules().iter())
+                    .all(|(name, molecule)| name == molecule.name())
+        };
+        if !matches {
+            return false;
+        }
+
+        for (set, cached) in self.sets.iter_mut().zip(cache.sets.into_iter()) {
+            if set.is_none() {
+                *set = cached.map(|visible| StructurePvs { visible });
             }
         }
 
-        // To reduce the list, the ranges are sorted and merged together
-        for i in 0..self.visible.len() {
-            pvs.visible[i].sort_by(|a, b| a.0.cmp(&b.0));
-            pvs.visible[i] = compress_ranges(pvs.visible[i].clone(), 0);
+        true
+    }
+
+    /// Loads the sets from the cache file at `path`. Returns whether the cache was used, a missing or outdated
+    /// cache is not an error.
+    pub fn load_cache<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<bool> {
+        match PvsCache::read(path) {
+            Ok(cache) => Ok(self.apply_cache(cache)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
         }
     }
 
-    pub fn ranges_limit(&self) -> usize {
-        self.ranges_limit
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.to_cache().write(path)
     }
 }
 /// One potentially visible set of field of them.
@@ -536,43 +714,337 @@ pub struct StructurePvs {
     pub visible: Vec<Vec<(u32, u32)>>,
 }
 
-pub fn list_to_ranges(list: &[u32]) -> Vec<(u32, u32)> {
-    let mut ranges = Vec::new();
+/// What drawing the instances in the gaps between ranges of instances of a molecule costs.
+#[derive(Clone, Debug, Default)]
+pub struct GapCost {
+    /// Atoms drawn for each instance.
+    pub atoms: u32,
 
-    let mut start = 0;
-    for (index, value) in list.iter().enumerate() {
-        if (index == list.len() - 1) || (*value != list[index + 1] - 1) {
-            ranges.push((list[start], *value + 1));
-            start = index + 1;
+    /// Instances starting groups, like faces of `hilbert::sort_by_hilbert`, that merged gaps must not cross.
+    pub boundaries: Vec<u32>,
 
-            if start >= list.len() - 1 {
-                break;
+    /// Gaps of exempt molecules are never merged.
+    pub exempt: bool,
+}
+
+/// Reduces the ranges of visible instances of each molecule to at most `ranges_limit` ranges in total, by merging
+/// the ranges around the gaps that cost the least to draw, `instances in the gap * atoms of an instance`. Gaps of
+/// exempt molecules and gaps crossing boundaries are left alone, so the result can have more ranges than the limit.
+///
+/// # Arguments
+///
+/// * `visible` - ranges of each molecule, sorted by their starts.
+/// * `costs` - costs of each molecule.
+/// * `ranges_limit` - upper bound of ranges in the result.
+///
+pub fn reduce_ranges(
+    visible: &[Vec<(u32, u32)>],
+    costs: &[GapCost],
+    ranges_limit: usize,
+) -> Vec<Vec<(u32, u32)>> {
+    assert_eq!(visible.len(), costs.len(), "every molecule needs its cost");
+
+    let visible: Vec<Vec<(u32, u32)>> = visible
+        .iter()
+        .map(|ranges| compress_ranges(ranges.clone(), 0))
+        .collect();
+
+    // Gaps that can be merged by their cost, molecule and the range following them
+    let mut gaps: Vec<(u64, usize, usize)> = Vec::new();
+    for (molecule_id, (ranges, cost)) in visible.iter().zip(costs.iter()).enumerate() {
+        if cost.exempt {
+            continue;
+        }
+
+        for range_id in 1..ranges.len() {
+            let (start, end) = (ranges[range_id - 1].1, ranges[range_id].0);
+            if cost
+                .boundaries
+                .iter()
+                .any(|boundary| *boundary >= start && *boundary <= end)
+            {
+                continue;
+            }
+
+            let instances = (end - start) as u64;
+            gaps.push((instances * cost.atoms as u64, molecule_id, range_id));
+        }
+    }
+
+    // Cheapest first, equal ones in the order of molecules and ranges
+    gaps.sort();
+
+    let ranges_count: usize = visible.iter().map(|ranges| ranges.len()).sum();
+    let merged_count = ranges_count.saturating_sub(ranges_limit).min(gaps.len());
+
+    let mut merged: Vec<Vec<bool>> = visible
+        .iter()
+        .map(|ranges| vec![false; ranges.len()])
+        .collect();
+    for (_, molecule_id, range_id) in gaps[..merged_count].iter() {
+        merged[*molecule_id][*range_id] = true;
+    }
+
+    visible
+        .iter()
+        .zip(merged.iter())
+        .map(|(ranges, merged)| {
+            let mut reduced: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+            for (range, merged) in ranges.iter().zip(merged.iter()) {
+                match reduced.last_mut() {
+                    Some(last) if *merged => last.1 = range.1,
+                    _ => reduced.push(*range),
+                }
             }
+
+            reduced
+        })
+        .collect()
+}
+
+/// Converts sorted indices into ranges of consecutive ones.
+pub fn list_to_ranges(list: &[u32]) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+
+    for value in list.iter() {
+        match ranges.last_mut() {
+            Some(last) if *value <= last.1 => last.1 = last.1.max(*value + 1),
+            _ => ranges.push((*value, *value + 1)),
         }
     }
 
     ranges
 }
 
+/// Merges ranges sorted by their starts which overlap or are apart by at most `threshold`.
 pub fn compress_ranges(list: Vec<(u32, u32)>, threshold: u32) -> Vec<(u32, u32)> {
-    if list.is_empty() {
-        return Vec::new();
+    let mut compressed: Vec<(u32, u32)> = Vec::with_capacity(list.len());
+
+    for range in list.into_iter() {
+        match compressed.last_mut() {
+            Some(last) if range.0 <= last.1.saturating_add(threshold) => {
+                last.1 = last.1.max(range.1)
+            }
+            _ => compressed.push(range),
+        }
+    }
+
+    compressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(atoms: u32) -> GapCost {
+        GapCost {
+            atoms,
+            ..GapCost::default()
+        }
     }
 
-    let mut new_list = Vec::new();
+    fn instances(ranges: &[(u32, u32)]) -> Vec<u32> {
+        ranges.iter().flat_map(|range| range.0..range.1).collect()
+    }
+
+    #[test]
+    fn list_to_ranges_edge_cases() {
+        assert_eq!(list_to_ranges(&[]), vec![]);
+        assert_eq!(list_to_ranges(&[0]), vec![(0, 1)]);
+        assert_eq!(list_to_ranges(&[7]), vec![(7, 8)]);
+        assert_eq!(list_to_ranges(&[3, 4, 5]), vec![(3, 6)]);
+        assert_eq!(list_to_ranges(&[1, 2, 5, 7]), vec![(1, 3), (5, 6), (7, 8)]);
+        assert_eq!(list_to_ranges(&[1, 3]), vec![(1, 2), (3, 4)]);
+        assert_eq!(list_to_ranges(&[2, 2, 3, 3]), vec![(2, 4)]);
+    }
 
-    let mut start = list[0].0;
-    for (index, range) in list.iter().enumerate() {
-        if index >= list.len() - 1 {
-            new_list.push((start, range.1));
-            break;
+    #[test]
+    fn list_to_ranges_all_subsets() {
+        for subset in 0u32..1 << 10 {
+            let list: Vec<u32> = (0..10).filter(|i| subset & (1 << i) != 0).collect();
+            let ranges = list_to_ranges(&list);
+
+            assert_eq!(instances(&ranges), list);
+            for pair in ranges.windows(2) {
+                assert!(pair[0].1 < pair[1].0, "{:?} are not apart", pair);
+            }
         }
+    }
+
+    #[test]
+    fn compress_ranges_edge_cases() {
+        assert_eq!(compress_ranges(vec![], 0), vec![]);
+        assert_eq!(compress_ranges(vec![(4, 6)], 0), vec![(4, 6)]);
+        assert_eq!(compress_ranges(vec![(4, 6)], 10), vec![(4, 6)]);
+
+        // Touching ranges are merged even without a threshold
+        assert_eq!(compress_ranges(vec![(0, 2), (2, 3)], 0), vec![(0, 3)]);
+        assert_eq!(
+            compress_ranges(vec![(0, 2), (3, 4)], 0),
+            vec![(0, 2), (3, 4)]
+        );
+
+        // Gaps up to the threshold are merged
+        assert_eq!(
+            compress_ranges(vec![(0, 2), (4, 5), (8, 9)], 2),
+            vec![(0, 5), (8, 9)]
+        );
+        assert_eq!(
+            compress_ranges(vec![(0, 2), (4, 5), (8, 9)], 3),
+            vec![(0, 9)]
+        );
+
+        // Overlapping and contained ranges
+        assert_eq!(
+            compress_ranges(vec![(0, 5), (2, 3), (4, 8)], 0),
+            vec![(0, 8)]
+        );
+        assert_eq!(
+            compress_ranges(vec![(0, 5), (1, 2), (6, 7)], 0),
+            vec![(0, 5), (6, 7)]
+        );
+
+        // No overflow close to the end of the index space
+        assert_eq!(
+            compress_ranges(vec![(0, u32::MAX - 1), (u32::MAX - 1, u32::MAX)], u32::MAX),
+            vec![(0, u32::MAX)]
+        );
+    }
+
+    #[test]
+    fn reduce_ranges_under_limit() {
+        let visible = vec![vec![(0, 2), (4, 6)], vec![], vec![(1, 2)]];
+        let costs = vec![cost(10), cost(10), cost(10)];
+
+        assert_eq!(reduce_ranges(&visible, &costs, 3), visible);
+        assert_eq!(reduce_ranges(&visible, &costs, 100), visible);
+        assert_eq!(reduce_ranges(&[], &[], 0), Vec::<Vec<(u32, u32)>>::new());
+    }
+
+    #[test]
+    fn reduce_ranges_merges_cheapest_gaps() {
+        // Gaps of 1 and 3 instances of 10 atoms, and of 2 instances of 4 atoms
+        let visible = vec![vec![(0, 1), (2, 3), (6, 7)], vec![(0, 1), (3, 4)]];
+        let costs = vec![cost(10), cost(4)];
+
+        assert_eq!(
+            reduce_ranges(&visible, &costs, 4),
+            vec![vec![(0, 1), (2, 3), (6, 7)], vec![(0, 4)]]
+        );
+        assert_eq!(
+            reduce_ranges(&visible, &costs, 3),
+            vec![vec![(0, 3), (6, 7)], vec![(0, 4)]]
+        );
+        assert_eq!(
+            reduce_ranges(&visible, &costs, 0),
+            vec![vec![(0, 7)], vec![(0, 4)]]
+        );
+    }
+
+    #[test]
+    fn reduce_ranges_sorts_gaps_by_cost() {
+        // Gaps of decreasing length, only the shortest one is merged
+        let visible = vec![vec![(0, 1), (5, 6), (9, 10), (12, 13)]];
+
+        assert_eq!(
+            reduce_ranges(&visible, &[cost(1)], 3),
+            vec![vec![(0, 1), (5, 6), (9, 13)]]
+        );
+        assert_eq!(
+            reduce_ranges(&visible, &[cost(1)], 2),
+            vec![vec![(0, 1), (5, 13)]]
+        );
+    }
+
+    #[test]
+    fn reduce_ranges_costs_do_not_overflow() {
+        // A gap of 2^20 instances of 2^20 atoms costs more than fits into u32
+        let visible = vec![
+            vec![(0, 1), (1 << 20 | 1, 1 << 20 | 2)],
+            vec![(0, 1), (3, 4)],
+        ];
+        let costs = vec![cost(1 << 20), cost(1 << 20)];
+
+        assert_eq!(
+            reduce_ranges(&visible, &costs, 3),
+            vec![visible[0].clone(), vec![(0, 4)]]
+        );
+    }
+
+    #[test]
+    fn reduce_ranges_skips_exempt_molecules() {
+        let visible = vec![vec![(0, 1), (2, 3)], vec![(0, 1), (5, 6)]];
+        let costs = vec![
+            GapCost {
+                atoms: 1,
+                exempt: true,
+                ..GapCost::default()
+            },
+            cost(1),
+        ];
+
+        assert_eq!(
+            reduce_ranges(&visible, &costs, 0),
+            vec![visible[0].clone(), vec![(0, 6)]]
+        );
+    }
+
+    #[test]
+    fn reduce_ranges_does_not_cross_boundaries() {
+        let visible = vec![vec![(0, 1), (2, 3), (4, 5), (6, 7)]];
 
-        if list[index + 1].0 - range.1 > threshold {
-            new_list.push((start, range.1));
-            start = list[index + 1].0;
+        for boundary in [1, 2].iter() {
+            let costs = vec![GapCost {
+                atoms: 1,
+                boundaries: vec![*boundary],
+                exempt: false,
+            }];
+
+            assert_eq!(
+                reduce_ranges(&visible, &costs, 0),
+                vec![vec![(0, 1), (2, 7)]]
+            );
         }
+
+        let costs = vec![GapCost {
+            atoms: 1,
+            boundaries: vec![0, 3, 7],
+            exempt: false,
+        }];
+        assert_eq!(
+            reduce_ranges(&visible, &costs, 0),
+            vec![vec![(0, 3), (4, 7)]]
+        );
+    }
+
+    #[test]
+    fn reduce_ranges_merges_touching_ranges() {
+        let visible = vec![vec![(0, 2), (2, 3), (5, 6)]];
+
+        assert_eq!(
+            reduce_ranges(&visible, &[cost(1)], 10),
+            vec![vec![(0, 3), (5, 6)]]
+        );
     }
 
-    new_list
+    #[test]
+    fn reduce_ranges_all_subsets() {
+        for subset in 0u32..1 << 8 {
+            let list: Vec<u32> = (0..8).filter(|i| subset & (1 << i) != 0).collect();
+            let visible = vec![list_to_ranges(&list)];
+
+            for limit in 0..5 {
+                let reduced = reduce_ranges(&visible, &[cost(3)], limit);
+
+                // Visible instances stay, as few ranges as allowed remain
+                let reduced_instances = instances(&reduced[0]);
+                assert!(list.iter().all(|i| reduced_instances.contains(i)));
+                assert_eq!(reduced[0].len(), visible[0].len().min(limit.max(1)));
+                if let (Some(first), Some(last)) = (visible[0].first(), visible[0].last()) {
+                    assert_eq!(reduced[0].first().unwrap().0, first.0);
+                    assert_eq!(reduced[0].last().unwrap().1, last.1);
+                }
+            }
+        }
+    }
 }
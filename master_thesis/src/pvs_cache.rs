@@ -0,0 +1,211 @@
+//! Cache file of a field of potentially visible sets.
+//!
+//! All numbers are little endian:
+//!
+//! magic "RPVC", version u32, structure hash u64, views count u32, shells count u32, per shell its distance f32,
+//! ranges limit u64, exempt molecules count u32, per exempt molecule its name (length u32, UTF-8 bytes),
+//! molecules count u32, per molecule its name (length u32, UTF-8 bytes),
+//! sets count u32, per set a presence byte and if present, per molecule its ranges (count u32, pairs of u32).
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC: [u8; 4] = *b"RPVC";
+pub const VERSION: u32 = 4;
+
+/// Potentially visible sets of a structure as stored on disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvsCache {
+    /// `Structure::transforms_hash` of the structure the sets were computed for.
+    pub hash: u64,
+    /// Number of views of the field, distributed on a Fibonacci sphere.
+    pub views_count: usize,
+
+    /// Distances of the shells of the field, in bounding radii of the structure.
+    pub shells: Vec<f32>,
+    pub ranges_limit: usize,
+
+    /// Names of the molecules whose gaps were not merged.
+    pub exempt: Vec<String>,
+
+    /// Names of the molecules, in the order of the ranges of each set.
+    pub molecules: Vec<String>,
+
+    /// Ranges of visible instances of each molecule, for each view. `None` for views not computed.
+    pub sets: Vec<Option<Vec<Vec<(u32, u32)>>>>,
+}
+
+/// Path of the cache file of the structure at `structure_path`, stored next to it.
+pub fn cache_path<P: AsRef<Path>>(structure_path: P) -> PathBuf {
+    structure_path.as_ref().with_extension("pvs")
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            return Err(invalid("unexpected end of the PVS cache"));
+        }
+
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Count of following items, each at least `item_size` bytes long.
+    fn count(&mut self, item_size: usize) -> Result<usize> {
+        let count = self.u32()? as usize;
+        if count * item_size > self.bytes.len() {
+            return Err(invalid("count exceeds the size of the PVS cache"));
+        }
+
+        Ok(count)
+    }
+
+    /// Count of following names and the names, each its length and UTF-8 bytes.
+    fn names(&mut self) -> Result<Vec<String>> {
+        let count = self.count(4)?;
+
+        let mut names = Vec::with_capacity(count);
+        for _ in 0..count {
+            let length = self.count(1)?;
+            let name = std::str::from_utf8(self.take(length)?)
+                .map_err(|_| invalid("molecule name is not UTF-8"))?;
+            names.push(name.to_string());
+        }
+
+        Ok(names)
+    }
+}
+
+fn push_names(bytes: &mut Vec<u8>, names: &[String]) {
+    bytes.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    for name in names.iter() {
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+    }
+}
+
+impl PvsCache {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.hash.to_le_bytes());
+        bytes.extend_from_slice(&(self.views_count as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.shells.len() as u32).to_le_bytes());
+        for shell in self.shells.iter() {
+            bytes.extend_from_slice(&shell.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.ranges_limit as u64).to_le_bytes());
+        push_names(&mut bytes, &self.exempt);
+
+        push_names(&mut bytes, &self.molecules);
+
+        bytes.extend_from_slice(&(self.sets.len() as u32).to_le_bytes());
+        for set in self.sets.iter() {
+            match set {
+                Some(visible) => {
+                    bytes.push(1);
+                    for ranges in visible.iter() {
+                        bytes.extend_from_slice(&(ranges.len() as u32).to_le_bytes());
+                        for (start, end) in ranges.iter() {
+                            bytes.extend_from_slice(&start.to_le_bytes());
+                            bytes.extend_from_slice(&end.to_le_bytes());
+                        }
+                    }
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a PVS cache"));
+        }
+        if reader.u32()? != VERSION {
+            return Err(invalid("unsupported version of the PVS cache"));
+        }
+
+        let hash = reader.u64()?;
+        let views_count = reader.u32()? as usize;
+        let shells_count = reader.count(4)?;
+        let mut shells = Vec::with_capacity(shells_count);
+        for _ in 0..shells_count {
+            shells.push(f32::from_bits(reader.u32()?));
+        }
+        let ranges_limit = reader.u64()? as usize;
+        let exempt = reader.names()?;
+
+        let molecules = reader.names()?;
+        let molecules_count = molecules.len();
+
+        let sets_count = reader.count(1)?;
+        let mut sets = Vec::with_capacity(sets_count);
+        for _ in 0..sets_count {
+            if reader.u8()? == 0 {
+                sets.push(None);
+                continue;
+            }
+
+            let mut visible = Vec::with_capacity(molecules_count);
+            for _ in 0..molecules_count {
+                let ranges_count = reader.count(8)?;
+                let mut ranges = Vec::with_capacity(ranges_count);
+                for _ in 0..ranges_count {
+                    ranges.push((reader.u32()?, reader.u32()?));
+                }
+                visible.push(ranges);
+            }
+            sets.push(Some(visible));
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(invalid("trailing data in the PVS cache"));
+        }
+
+        Ok(Self {
+            hash,
+            views_count,
+            shells,
+            ranges_limit,
+            exempt,
+            molecules,
+            sets,
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::decode(&std::fs::read(path)?)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.encode())
+    }
+}
@@ -0,0 +1,508 @@
+//! CPU reference implementation of the computation of potentially visible sets.
+//!
+//! Spheres are drawn as the same camera facing triangles the depth pipeline of `StructurePvsModule` draws, into a
+//! depth buffer of the same resolution and with the same depth test. Visible instances therefore match those of
+//! `StructurePvsField::compute`, up to pixels on the edges of triangles that the rasterizer of a GPU may treat
+//! differently.
+use nalgebra_glm::*;
+use rpdb::FromFile;
+
+use std::path::Path;
+
+use crate::hilbert;
+use crate::pvs::{list_to_ranges, shell_camera, StructurePvs, DEPTH_RESOLUTION};
+use crate::structure::sorted_molecules;
+use crate::*;
+
+/// Corners of the triangle drawn for a sphere of radius 1, as in the vertex shader of the depth pipeline.
+const BILLBOARD: [[f32; 2]; 3] = [[-0.86, -0.5], [0.86, -0.5], [0.0, 1.0]];
+
+/// Spheres drawn for each instance of a molecule.
+#[derive(Copy, Clone, Debug)]
+pub enum PvsSpheres {
+    /// One sphere bounding the whole molecule.
+    BoundingSphere,
+
+    /// Atoms of the level of detail, or of the last one if the molecule has fewer. The GPU path draws level 0.
+    Lod(usize),
+}
+
+/// Spheres and transforms of a molecule kept in memory for the CPU computation.
+pub struct CpuMolecule {
+    name: String,
+
+    /// Spheres in the local coordinate system of the molecule.
+    spheres: Vec<Vec4>,
+
+    /// Transforms of instances, in the same order as on a GPU.
+    transforms: Vec<Mat4>,
+}
+
+impl CpuMolecule {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn spheres(&self) -> &[Vec4] {
+        &self.spheres
+    }
+
+    pub fn transforms(&self) -> &[Mat4] {
+        &self.transforms
+    }
+
+    /// Calls `fragment` with the instance, the pixel index and the depth of every fragment of the triangles of the
+    /// spheres of all instances.
+    fn rasterize<F: FnMut(usize, usize, f32)>(
+        &self,
+        projection_view: &Mat4,
+        camera_right: &Vec3,
+        camera_up: &Vec3,
+        mut fragment: F,
+    ) {
+        let size = DEPTH_RESOLUTION as f32;
+
+        for (instance, transform) in self.transforms.iter().enumerate() {
+            for sphere in self.spheres.iter() {
+                let center = transform * vec4(sphere.x, sphere.y, sphere.z, 1.0);
+
+                // Triangles lie in a plane parallel to the image plane, so all of their fragments have the depth
+                // of the center.
+                let clip = projection_view * center;
+                let depth = clip.z / clip.w;
+                if !(0.0..=1.0).contains(&depth) {
+                    continue;
+                }
+
+                let mut corners = [vec2(0.0, 0.0); 3];
+                for (corner, vertex) in corners.iter_mut().zip(BILLBOARD.iter()) {
+                    let vertex = sphere.w * vec2(vertex[0], vertex[1]);
+                    let position = center.xyz() + vertex.x * *camera_right + vertex.y * *camera_up;
+                    let clip = projection_view * vec4(position.x, position.y, position.z, 1.0);
+
+                    *corner = vec2(
+                        (clip.x / clip.w + 1.0) * 0.5 * size,
+                        (1.0 - clip.y / clip.w) * 0.5 * size,
+                    );
+                }
+
+                rasterize_triangle(&corners, |x, y| {
+                    fragment(instance, y * DEPTH_RESOLUTION as usize + x, depth)
+                });
+            }
+        }
+    }
+}
+
+/// Calls `pixel` with the coordinates of every pixel whose center lies inside of the triangle given in pixels.
+fn rasterize_triangle<F: FnMut(usize, usize)>(corners: &[Vec2; 3], mut pixel: F) {
+    let edge = |a: &Vec2, b: &Vec2, p: &Vec2| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+
+    let area = edge(&corners[0], &corners[1], &corners[2]);
+    if area == 0.0 || area.is_nan() {
+        return;
+    }
+
+    let last = DEPTH_RESOLUTION as f32 - 1.0;
+    let min = corners[0].inf(&corners[1]).inf(&corners[2]);
+    let max = corners[0].sup(&corners[1]).sup(&corners[2]);
+    let (x_start, x_end) = (
+        (min.x - 0.5).ceil().max(0.0),
+        (max.x - 0.5).floor().min(last),
+    );
+    let (y_start, y_end) = (
+        (min.y - 0.5).ceil().max(0.0),
+        (max.y - 0.5).floor().min(last),
+    );
+    if x_start > x_end || y_start > y_end {
+        return;
+    }
+
+    for y in y_start as usize..=y_end as usize {
+        for x in x_start as usize..=x_end as usize {
+            let p = vec2(x as f32 + 0.5, y as f32 + 0.5);
+
+            let inside = (0..3).all(|i| {
+                let weight = edge(&corners[(i + 1) % 3], &corners[(i + 2) % 3], &p);
+                weight * area.signum() >= 0.0
+            });
+            if inside {
+                pixel(x, y);
+            }
+        }
+    }
+}
+
+/// Molecules of a structure as spheres in memory, for computing potentially visible sets without a GPU.
+pub struct CpuStructure {
+    molecules: Vec<CpuMolecule>,
+
+    bounding_radius: f32,
+}
+
+impl CpuStructure {
+    /// Loads the structure at `path` with molecules and their instances in the same order as `Structure::from_ron`,
+    /// so that the computed sets apply to it.
+    pub fn from_file<P: AsRef<Path>>(path: P, spheres: PvsSpheres) -> Self {
+        let structure_file = rpdb::structure::Structure::from_file(&path);
+
+        let mut molecules = Vec::new();
+        let mut bounding_radius: f32 = 0.0;
+
+        for (name, transforms) in sorted_molecules(&structure_file) {
+            let molecule =
+                rpdb::molecule::Molecule::from_file(rpdb::structure::molecule_path(&path, &name));
+
+            let bounding_box = molecule.bounding_box();
+            let molecule_radius = distance(&bounding_box.max, &bounding_box.min) / 2.0;
+
+            let spheres = match spheres {
+                PvsSpheres::BoundingSphere => {
+                    let center = (bounding_box.min + bounding_box.max) / 2.0;
+                    vec![vec4(center.x, center.y, center.z, molecule_radius)]
+                }
+                PvsSpheres::Lod(lod) => {
+                    let lods = molecule.lods();
+                    lods[lod.min(lods.len() - 1)].atoms().to_vec()
+                }
+            };
+
+            let transforms = hilbert::sort_by_hilbert(&transforms).0;
+            for transform in transforms.iter() {
+                bounding_radius =
+                    bounding_radius.max(length(&transform.column(3).xyz()) + molecule_radius);
+            }
+
+            molecules.push(CpuMolecule {
+                name,
+                spheres,
+                transforms,
+            });
+        }
+
+        Self {
+            molecules,
+            bounding_radius,
+        }
+    }
+
+    pub fn molecules(&self) -> &[CpuMolecule] {
+        &self.molecules
+    }
+
+    pub fn bounding_radius(&self) -> f32 {
+        self.bounding_radius
+    }
+
+    /// Sorted indices of instances of each molecule with a fragment passing the depth test from the view at
+    /// `spherical_coords` in the shell at `shell` bounding radii, seen by the same camera as the one of
+    /// `StructurePvsField`.
+    pub fn visible_instances(&self, spherical_coords: TVec2<f64>, shell: f32) -> Vec<Vec<u32>> {
+        let (projection, distance) = shell_camera(self.bounding_radius, shell);
+
+        // `RotationCamera` at the distance of the shell
+        let eye = distance as f64 * spherical_to_cartesian(&spherical_coords);
+        let eye = vec3(eye.x as f32, eye.y as f32, eye.z as f32);
+        let view = look_at_rh(&eye, &vec3(0.0, 0.0, 0.0), &vec3(0.0, 1.0, 0.0));
+        let projection_view = projection * view;
+
+        let camera_right = vec3(view[(0, 0)], view[(0, 1)], view[(0, 2)]);
+        let camera_up = vec3(view[(1, 0)], view[(1, 1)], view[(1, 2)]);
+
+        // The first pass writes the depth buffer cleared to 0.0, keeping the greater depth like the pipeline.
+        let mut depth = vec![0.0f32; (DEPTH_RESOLUTION * DEPTH_RESOLUTION) as usize];
+        for molecule in self.molecules.iter() {
+            molecule.rasterize(
+                &projection_view,
+                &camera_right,
+                &camera_up,
+                |_, pixel, z| {
+                    if z > depth[pixel] {
+                        depth[pixel] = z;
+                    }
+                },
+            );
+        }
+
+        // The second pass marks instances with a fragment passing the depth test.
+        self.molecules
+            .iter()
+            .map(|molecule| {
+                let mut visible = vec![false; molecule.transforms.len()];
+                molecule.rasterize(
+                    &projection_view,
+                    &camera_right,
+                    &camera_up,
+                    |instance, pixel, z| {
+                        if z >= depth[pixel] {
+                            visible[instance] = true;
+                        }
+                    },
+                );
+
+                visible
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(instance, visible)| {
+                        if *visible {
+                            Some(instance as u32)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Potentially visible set from the view at `spherical_coords` in the shell at `shell` bounding radii, before it
+    /// is reduced by `StructurePvsField`.
+    pub fn pvs(&self, spherical_coords: TVec2<f64>, shell: f32) -> StructurePvs {
+        let visible = self
+            .visible_instances(spherical_coords, shell)
+            .iter()
+            .map(|instances| list_to_ranges(instances))
+            .collect();
+
+        StructurePvs { visible }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraUbo;
+    use crate::pvs::{StructurePvsModule, DEFAULT_SHELLS};
+    use crate::structure::Structure;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rpdb::molecule::{Molecule, MoleculeLod};
+    use rpdb::{BoundingBox, ToRon};
+    use wgpu::*;
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    /// Writes a structure with the molecules and positions of their instances into a temporary directory, which
+    /// the test removes with `remove_structure`.
+    fn write_structure(directory: &str, molecules: &[(&str, Vec<Vec4>, Vec<Mat4>)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("{}_{}", directory, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut transforms = HashMap::new();
+        for (name, atoms, instances) in molecules.iter() {
+            let radius = atoms.iter().fold(0.0f32, |radius, atom| {
+                radius.max(length(&atom.xyz()) + atom.w)
+            });
+
+            Molecule {
+                name: name.to_string(),
+                bounding_box: BoundingBox {
+                    min: vec3(-radius, -radius, -radius),
+                    max: vec3(radius, radius, radius),
+                },
+                lods: vec![MoleculeLod::new(atoms.clone(), 0.0)],
+            }
+            .to_ron(directory.join(format!("{}.ron", name)));
+
+            transforms.insert(name.to_string(), instances.clone());
+        }
+
+        let path = directory.join("structure.ron");
+        rpdb::structure::Structure::new(transforms).to_ron(&path);
+
+        path
+    }
+
+    fn remove_structure(path: &Path) {
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    fn instances(ranges: &[(u32, u32)]) -> Vec<u32> {
+        ranges.iter().flat_map(|range| range.0..range.1).collect()
+    }
+
+    #[test]
+    fn occluded_instance_is_not_visible() {
+        let atom = vec![vec4(0.0, 0.0, 0.0, 1.0)];
+        let path = write_structure(
+            "pvs_cpu_occlusion",
+            &[(
+                "A",
+                atom,
+                vec![
+                    translation(&vec3(-5.0, 0.0, 0.0)),
+                    translation(&vec3(5.0, 0.0, 0.0)),
+                    translation(&vec3(0.0, 0.0, 5.0)),
+                ],
+            )],
+        );
+        let structure = CpuStructure::from_file(&path, PvsSpheres::Lod(0));
+        remove_structure(&path);
+
+        // Looking along the x axis, the instances on it cover each other.
+        let far = std::f32::INFINITY;
+        let visible = structure.visible_instances(vec2(0.0, 90.0f64.to_radians()), far);
+        let transforms = structure.molecules()[0].transforms();
+        let on_axis = visible[0]
+            .iter()
+            .filter(|instance| transforms[**instance as usize][(2, 3)] == 0.0)
+            .count();
+
+        assert_eq!(visible[0].len(), 2);
+        assert_eq!(on_axis, 1);
+
+        // Looking along the z axis, none of them do.
+        let visible =
+            structure.visible_instances(vec2(90.0f64.to_radians(), 90.0f64.to_radians()), far);
+        assert_eq!(visible[0], vec![0, 1, 2]);
+
+        // Close up in perspective, the closer instance on the axis covers the farther one.
+        let visible = structure.visible_instances(vec2(0.0, 90.0f64.to_radians()), 1.5);
+        let closer = visible[0]
+            .iter()
+            .filter(|instance| transforms[**instance as usize][(0, 3)] > 0.0)
+            .count();
+
+        assert_eq!(visible[0].len(), 2);
+        assert_eq!(closer, 1);
+    }
+
+    #[test]
+    fn cpu_matches_gpu() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut random_molecule = |atoms_count: usize, instances_count: usize| {
+            let atoms = (0..atoms_count)
+                .map(|_| {
+                    vec4(
+                        rng.gen_range(-4.0..4.0),
+                        rng.gen_range(-4.0..4.0),
+                        rng.gen_range(-4.0..4.0),
+                        rng.gen_range(1.0..2.0),
+                    )
+                })
+                .collect::<Vec<Vec4>>();
+            let instances = (0..instances_count)
+                .map(|_| {
+                    let position = vec3(
+                        rng.gen_range(-60.0..60.0),
+                        rng.gen_range(-60.0..60.0),
+                        rng.gen_range(-60.0..60.0),
+                    );
+                    let axis = normalize(&vec3(
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                    ));
+                    translation(&position)
+                        * rotation(rng.gen_range(0.0..std::f32::consts::TAU), &axis)
+                })
+                .collect::<Vec<Mat4>>();
+
+            (atoms, instances)
+        };
+        let (a_atoms, a_instances) = random_molecule(16, 300);
+        let (b_atoms, b_instances) = random_molecule(4, 200);
+        let path = write_structure(
+            "pvs_cpu_gpu",
+            &[("A", a_atoms, a_instances), ("B", b_atoms, b_instances)],
+        );
+
+        let instance = Instance::new(BackendBit::PRIMARY);
+        let adapter =
+            futures::executor::block_on(instance.request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                compatible_surface: None,
+            }));
+        let adapter = match adapter {
+            Some(adapter) => adapter,
+            None => {
+                println!("No GPU adapter, skipping the comparison with the GPU.");
+                remove_structure(&path);
+                return;
+            }
+        };
+        let (device, queue) = futures::executor::block_on(adapter.request_device(
+            &DeviceDescriptor {
+                label: None,
+                features: Features::empty(),
+                limits: Limits::default(),
+            },
+            None,
+        ))
+        .expect("Could not create a device.");
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::all(),
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(CameraUbo::size()),
+                    },
+                    count: None,
+                }],
+            });
+        let molecule_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::all(),
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let per_molecule_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[molecule_entry(0), molecule_entry(1)],
+            });
+
+        let structure = Structure::from_ron(&device, &path, &per_molecule_bind_group_layout);
+        let pvs_module = Rc::new(StructurePvsModule::new(
+            &device,
+            &camera_bind_group_layout,
+            &per_molecule_bind_group_layout,
+        ));
+        // Without a limit of ranges, the reduction leaves the sets as they are.
+        let mut field = pvs_module.pvs_field(
+            &device,
+            &camera_bind_group_layout,
+            Rc::new(RefCell::new(structure)),
+            16,
+            &DEFAULT_SHELLS,
+            usize::MAX,
+        );
+        let cpu_structure = CpuStructure::from_file(&path, PvsSpheres::Lod(0));
+        remove_structure(&path);
+
+        for index in 0..field.sets.len() {
+            futures::executor::block_on(field.compute(&device, &queue, index));
+            let gpu = field.sets[index].take().unwrap();
+
+            field.compute_cpu(&cpu_structure, index);
+            let cpu = field.sets[index].as_ref().unwrap();
+
+            for (gpu, cpu) in gpu.visible.iter().zip(cpu.visible.iter()) {
+                let (gpu, cpu) = (instances(gpu), instances(cpu));
+                let differences = gpu.iter().filter(|i| !cpu.contains(i)).count()
+                    + cpu.iter().filter(|i| !gpu.contains(i)).count();
+
+                // Instances seen only through pixels on the edges of triangles may differ.
+                assert!(
+                    differences <= 1 + gpu.len() / 50,
+                    "view {}: {} of {} visible instances differ",
+                    index,
+                    differences,
+                    gpu.len()
+                );
+            }
+        }
+    }
+}
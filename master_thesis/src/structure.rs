@@ -7,9 +7,10 @@
 ///!      | has multiple
 ///! Vec<Structure>
 use bytemuck::cast_slice;
-use nalgebra_glm::{distance, length, vec3, Vec3};
+use nalgebra_glm::{distance, length, vec3, Mat4, Vec3};
 use rpdb;
 use rpdb::BoundingBox;
+use rpdb::FromFile;
 use rpdb::FromRon;
 use wgpu::util::*;
 use wgpu::*;
@@ -78,6 +79,54 @@ impl Molecule {
         }
     }
 
+    /// Loads a memory-mapped binary molecule and uploads its atoms to the device directly from the mapping.
+    pub fn from_binary<P: AsRef<std::path::Path>>(device: &Device, path: P) -> Self {
+        let name = path.as_ref().file_stem().unwrap().to_str().unwrap();
+
+        let molecule =
+            rpdb::mapped::MappedMolecule::open(&path).unwrap_or_else(|e| panic!("{}", e));
+
+        let lods = molecule
+            .lods()
+            .iter()
+            .map(|lod| {
+                (
+                    lod.breakpoint,
+                    lod.atoms.start as u32 * 3..lod.atoms.end as u32 * 3,
+                )
+            })
+            .collect();
+
+        let atoms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: molecule.atoms_bytes(),
+            usage: BufferUsage::STORAGE,
+        });
+
+        let bounding_box = *molecule.bounding_box();
+        let bounding_radius = distance(&bounding_box.max, &bounding_box.min) / 2.0;
+
+        Self {
+            name: name.to_string(),
+            atoms,
+            lods,
+            bounding_box,
+            bounding_radius,
+            color: vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Loads a molecule in either the binary or the RON format.
+    pub fn from_file<P: AsRef<std::path::Path>>(device: &Device, path: P) -> Self {
+        let is_binary = rpdb::binary::is_binary_file(&path).unwrap_or_else(|e| panic!("{}", e));
+
+        if is_binary {
+            Self::from_binary(device, path)
+        } else {
+            Self::from_ron(device, path)
+        }
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -126,6 +175,41 @@ pub struct Structure {
 
     ///
     bounding_radius: f32,
+
+    /// Hash of the names of molecules and their transforms, identifying the structure in caches.
+    transforms_hash: u64,
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Continues the FNV-1a `hash` over the name of a molecule and its transforms. Unlike the hashers of the standard
+/// library, the result does not change between builds, so it can be stored in files.
+fn hash_transforms(mut hash: u64, name: &str, transforms: &[Mat4]) -> u64 {
+    let floats = transforms
+        .iter()
+        .flat_map(|transform| transform.as_slice().iter());
+    let bytes = name
+        .bytes()
+        .chain(std::iter::once(0))
+        .chain(floats.flat_map(|f| f.to_le_bytes().to_vec()));
+
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
+
+/// Molecules of the structure file ordered by their names, so that molecule indices are the same on every load.
+pub(crate) fn sorted_molecules(
+    structure_file: &rpdb::structure::Structure,
+) -> Vec<(String, Vec<Mat4>)> {
+    let mut molecules: Vec<(String, Vec<Mat4>)> = structure_file.flatten().into_iter().collect();
+    molecules.sort_by(|a, b| a.0.cmp(&b.0));
+
+    molecules
 }
 
 impl Structure {
@@ -134,7 +218,7 @@ impl Structure {
         path: P,
         per_molecule_bind_group_layout: &BindGroupLayout,
     ) -> Self {
-        let structure_file = rpdb::structure::Structure::from_ron(&path);
+        let structure_file = rpdb::structure::Structure::from_file(&path);
 
         let mut molecules = Vec::new();
 
@@ -145,12 +229,18 @@ impl Structure {
 
         let mut bounding_radius: f32 = 0.0;
 
-        for (molecule_name, molecule_model_matrices) in structure_file.molecules {
-            let new_molecule =
-                Molecule::from_ron(device, path.as_ref().with_file_name(molecule_name + ".ron"));
+        let mut transforms_hash = FNV_OFFSET_BASIS;
+
+        for (molecule_name, molecule_model_matrices) in sorted_molecules(&structure_file) {
+            let new_molecule = Molecule::from_file(
+                device,
+                rpdb::structure::molecule_path(&path, &molecule_name),
+            );
 
             let hilbert = hilbert::sort_by_hilbert(&molecule_model_matrices);
             let molecule_model_matrices = hilbert.0;
+            transforms_hash =
+                hash_transforms(transforms_hash, &molecule_name, &molecule_model_matrices);
             let molecule_model_matrices_len = molecule_model_matrices.len();
             let molecule_model_matrices = {
                 let mut matrices_flat: Vec<f32> = Vec::new();
@@ -214,6 +304,7 @@ impl Structure {
             bind_groups,
             bounding_box,
             bounding_radius,
+            transforms_hash,
         }
     }
 
@@ -222,7 +313,7 @@ impl Structure {
         path: P,
         per_molecule_bind_group_layout: &BindGroupLayout,
     ) -> (Self, Vec<BindGroup>) {
-        let structure_file = rpdb::structure::Structure::from_ron(&path);
+        let structure_file = rpdb::structure::Structure::from_file(&path);
 
         let mut molecules = Vec::new();
 
@@ -234,12 +325,18 @@ impl Structure {
 
         let mut bounding_radius: f32 = 0.0;
 
-        for (molecule_name, molecule_model_matrices) in structure_file.molecules {
-            let new_molecule =
-                Molecule::from_ron(device, path.as_ref().with_file_name(molecule_name + ".ron"));
+        let mut transforms_hash = FNV_OFFSET_BASIS;
+
+        for (molecule_name, molecule_model_matrices) in sorted_molecules(&structure_file) {
+            let new_molecule = Molecule::from_file(
+                device,
+                rpdb::structure::molecule_path(&path, &molecule_name),
+            );
 
             let hilbert = hilbert::sort_by_hilbert(&molecule_model_matrices);
             let molecule_model_matrices = hilbert.0;
+            transforms_hash =
+                hash_transforms(transforms_hash, &molecule_name, &molecule_model_matrices);
             let molecule_model_matrices_len = molecule_model_matrices.len();
             let molecule_model_matrices = {
                 let mut matrices_flat: Vec<f32> = Vec::new();
@@ -327,6 +424,7 @@ impl Structure {
                 bind_groups,
                 bounding_box,
                 bounding_radius,
+                transforms_hash,
             },
             return_bind_groups,
         )
@@ -359,4 +457,8 @@ impl Structure {
     pub fn bounding_radius(&self) -> f32 {
         self.bounding_radius
     }
+
+    pub fn transforms_hash(&self) -> u64 {
+        self.transforms_hash
+    }
 }
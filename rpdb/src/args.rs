@@ -0,0 +1,117 @@
+//! Command line arguments of the tools, which report malformed arguments with their usage and exit with code
+//! 2, and files that can not be read or written with code 1.
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Arguments of the process without the program name. Options are removed as they are taken, leaving the
+/// positional arguments.
+pub struct Args {
+    args: Vec<String>,
+    usage: &'static str,
+}
+
+impl Args {
+    /// Arguments of the process, reported with `usage` when malformed.
+    pub fn new(usage: &'static str) -> Args {
+        Args::from_vec(std::env::args().skip(1).collect(), usage)
+    }
+
+    fn from_vec(args: Vec<String>, usage: &'static str) -> Args {
+        Args { args, usage }
+    }
+
+    /// Removes `name` and the value following it from the arguments and returns the value.
+    pub fn option(&mut self, name: &str) -> Option<String> {
+        let index = self.args.iter().position(|arg| arg == name)?;
+        self.args.remove(index);
+
+        if index < self.args.len() {
+            Some(self.args.remove(index))
+        } else {
+            self.usage_error(&format!("{} expects a value", name))
+        }
+    }
+
+    /// Removes `name` and its value from the arguments and parses the value.
+    pub fn parse_option<T: FromStr>(&mut self, name: &str) -> Option<T>
+    where
+        T::Err: Display,
+    {
+        self.option(name).map(|value| self.parse(name, &value))
+    }
+
+    /// Removes `name` and its value from the arguments and parses the value as a comma separated list.
+    pub fn parse_list_option<T: FromStr>(&mut self, name: &str) -> Option<Vec<T>>
+    where
+        T::Err: Display,
+    {
+        self.option(name).map(|value| {
+            value
+                .split(',')
+                .map(|item| self.parse(name, item.trim()))
+                .collect()
+        })
+    }
+
+    /// Removes `name` from the arguments and returns whether it was present.
+    pub fn flag(&mut self, name: &str) -> bool {
+        let present = self.args.iter().any(|arg| arg == name);
+        self.args.retain(|arg| arg != name);
+
+        present
+    }
+
+    /// Parses the `value` given to the option `name`.
+    pub fn parse<T: FromStr>(&self, name: &str, value: &str) -> T
+    where
+        T::Err: Display,
+    {
+        value.parse().unwrap_or_else(|e| {
+            self.usage_error(&format!("invalid value {} of {}: {}", value, name, e))
+        })
+    }
+
+    /// Returns the positional arguments once all options were taken, rejecting those left over.
+    pub fn positional(&mut self) -> Vec<String> {
+        if let Some(unknown) = self.args.iter().find(|arg| arg.starts_with("--")) {
+            self.usage_error(&format!("unknown option {}", unknown));
+        }
+
+        std::mem::take(&mut self.args)
+    }
+
+    /// Reports malformed arguments together with the usage and exits.
+    pub fn usage_error(&self, message: &str) -> ! {
+        eprintln!("{}\n\n{}", message, self.usage);
+        std::process::exit(2);
+    }
+}
+
+/// Reports an error of reading or writing files and exits.
+pub fn fail<E: Display>(error: E) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_and_flags_are_taken() {
+        let args = [
+            "--views", "8", "a.ron", "--force", "--shells", "2,4", "b.ron",
+        ];
+        let mut args = Args::from_vec(args.iter().map(|arg| arg.to_string()).collect(), "");
+
+        assert!(args.flag("--force"));
+        assert!(!args.flag("--force"));
+        assert_eq!(args.parse_option::<u32>("--views"), Some(8));
+        assert_eq!(
+            args.parse_list_option::<f32>("--shells"),
+            Some(vec![2.0, 4.0])
+        );
+        assert_eq!(args.option("--exempt"), None);
+        assert_eq!(args.positional(), vec!["a.ron", "b.ron"]);
+    }
+}
@@ -0,0 +1,436 @@
+//! Expansion of biological assemblies described by PDB `REMARK 350 BIOMT` records or mmCIF
+//! `pdbx_struct_oper_list` operators into a `Structure`.
+use crate::atom::{molecule_from_records, record_atoms, AtomRecord};
+use crate::error::{Error, Result};
+use crate::mmcif::{self, CifBlock};
+use crate::molecule::Molecule;
+use crate::radii::RadiusTable;
+use crate::structure::Structure;
+use crate::{bounding_box, pdb};
+
+use nalgebra_glm::{translation, Mat4};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Set of operators applied to a set of chains.
+#[derive(Clone, Debug)]
+pub struct AssemblyPart {
+    pub chains: Vec<String>,
+    pub operators: Vec<Mat4>,
+}
+
+const APPLY_TO_CHAINS: &str = "APPLY THE FOLLOWING TO CHAINS:";
+const AND_CHAINS: &str = "AND CHAINS:";
+
+fn parse_chains(list: &str) -> Vec<String> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_string())
+        .collect()
+}
+
+/// Reads `REMARK 350` operators of the given biomolecule from PDB text.
+pub fn pdb_assembly(
+    text: &str,
+    biomolecule: &str,
+) -> std::result::Result<Vec<AssemblyPart>, String> {
+    let mut parts: Vec<AssemblyPart> = Vec::new();
+    let mut current_biomolecule = String::new();
+    let mut matrix = Mat4::identity();
+
+    for line in text.lines() {
+        let remark = match line.strip_prefix("REMARK 350") {
+            Some(remark) => remark.trim(),
+            None => continue,
+        };
+
+        if let Some(id) = remark.strip_prefix("BIOMOLECULE:") {
+            current_biomolecule = id.trim().to_string();
+            continue;
+        }
+
+        if current_biomolecule != biomolecule {
+            continue;
+        }
+
+        if let Some(index) = remark.find(APPLY_TO_CHAINS) {
+            parts.push(AssemblyPart {
+                chains: parse_chains(&remark[index + APPLY_TO_CHAINS.len()..]),
+                operators: Vec::new(),
+            });
+        } else if let Some(index) = remark.find(AND_CHAINS) {
+            if let Some(part) = parts.last_mut() {
+                part.chains
+                    .extend(parse_chains(&remark[index + AND_CHAINS.len()..]));
+            }
+        } else if remark.starts_with("BIOMT") {
+            let tokens: Vec<&str> = remark.split_whitespace().collect();
+            let row = match tokens[0] {
+                "BIOMT1" => 0,
+                "BIOMT2" => 1,
+                "BIOMT3" => 2,
+                _ => return Err(format!("invalid record {}", tokens[0])),
+            };
+            if tokens.len() < 6 {
+                return Err(format!("incomplete record {}", remark));
+            }
+
+            for column in 0..4 {
+                matrix[(row, column)] = tokens[column + 2]
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid value in record {}", remark))?;
+            }
+
+            if row == 2 {
+                let part = parts
+                    .last_mut()
+                    .ok_or_else(|| "BIOMT record before the list of chains".to_string())?;
+                part.operators.push(matrix);
+                matrix = Mat4::identity();
+            }
+        }
+    }
+
+    if parts.is_empty() {
+        return Err(format!("biomolecule {} not found", biomolecule));
+    }
+
+    Ok(parts)
+}
+
+/// Expands an operator expression such as `1`, `1,2,5`, `(1-60)` or `(1-60)(61-88)` into groups of operator ids.
+fn parse_oper_expression(expression: &str) -> std::result::Result<Vec<Vec<String>>, String> {
+    let groups: Vec<&str> = if expression.contains('(') {
+        expression
+            .split(['(', ')'].as_ref())
+            .map(|g| g.trim())
+            .filter(|g| !g.is_empty())
+            .collect()
+    } else {
+        vec![expression]
+    };
+    if groups.is_empty() {
+        return Err(format!("invalid operator expression {}", expression));
+    }
+
+    groups
+        .iter()
+        .map(|group| {
+            let mut ids = Vec::new();
+            for item in group.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+                let range: Vec<&str> = item.splitn(2, '-').collect();
+                match (
+                    range.first().and_then(|s| s.parse::<u32>().ok()),
+                    range.get(1).and_then(|s| s.parse::<u32>().ok()),
+                ) {
+                    (Some(start), Some(end)) => ids.extend((start..=end).map(|i| i.to_string())),
+                    _ => ids.push(item.to_string()),
+                }
+            }
+
+            if ids.is_empty() {
+                Err(format!("invalid operator expression {}", expression))
+            } else {
+                Ok(ids)
+            }
+        })
+        .collect()
+}
+
+/// Reads operators of the given assembly, or of the first one when `None`, from an mmCIF block.
+pub fn cif_assembly(
+    block: &CifBlock,
+    assembly_id: Option<&str>,
+) -> std::result::Result<Vec<AssemblyPart>, String> {
+    let column = |tag: &str| block.get(tag).ok_or_else(|| format!("missing {}", tag));
+
+    // Operators by their id
+    let ids = column("_pdbx_struct_oper_list.id")?;
+    let mut operators: HashMap<&str, Mat4> = HashMap::new();
+    for (row, id) in ids.iter().enumerate() {
+        let mut matrix = Mat4::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                matrix[(i, j)] = parse_operator_value(
+                    column(&format!(
+                        "_pdbx_struct_oper_list.matrix[{}][{}]",
+                        i + 1,
+                        j + 1
+                    ))?,
+                    row,
+                )?;
+            }
+            matrix[(i, 3)] = parse_operator_value(
+                column(&format!("_pdbx_struct_oper_list.vector[{}]", i + 1))?,
+                row,
+            )?;
+        }
+        operators.insert(id.as_str(), matrix);
+    }
+
+    let assembly_ids = column("_pdbx_struct_assembly_gen.assembly_id")?;
+    let expressions = column("_pdbx_struct_assembly_gen.oper_expression")?;
+    let asym_ids = column("_pdbx_struct_assembly_gen.asym_id_list")?;
+    if expressions.len() != assembly_ids.len() || asym_ids.len() != assembly_ids.len() {
+        return Err("_pdbx_struct_assembly_gen columns have different lengths".to_string());
+    }
+
+    let assembly_id = assembly_id
+        .map(|id| id.to_string())
+        .or_else(|| assembly_ids.first().cloned())
+        .ok_or_else(|| "no assemblies".to_string())?;
+
+    let mut parts = Vec::new();
+    for row in 0..assembly_ids.len() {
+        if assembly_ids[row] != assembly_id {
+            continue;
+        }
+
+        // Operators of consecutive groups are multiplied, the right-most group is applied first
+        let mut matrices = vec![Mat4::identity()];
+        for group in parse_oper_expression(&expressions[row])? {
+            let mut new_matrices = Vec::with_capacity(matrices.len() * group.len());
+            for matrix in matrices.iter() {
+                for id in group.iter() {
+                    let operator = operators
+                        .get(id.as_str())
+                        .ok_or_else(|| format!("unknown operator {}", id))?;
+                    new_matrices.push(matrix * operator);
+                }
+            }
+            matrices = new_matrices;
+        }
+
+        parts.push(AssemblyPart {
+            chains: parse_chains(&asym_ids[row]),
+            operators: matrices,
+        });
+    }
+
+    if parts.is_empty() {
+        return Err(format!("assembly {} not found", assembly_id));
+    }
+
+    Ok(parts)
+}
+
+fn parse_operator_value(column: &[String], row: usize) -> std::result::Result<f32, String> {
+    let value = column
+        .get(row)
+        .ok_or_else(|| format!("missing operator value of operator {}", row + 1))?;
+
+    value
+        .parse::<f32>()
+        .map_err(|_| format!("invalid operator value {:?}", value))
+}
+
+/// Creates a structure with one molecule per chain, instanced once per operator applied to the chain.
+/// Molecules are named `<name>_<chain>` and returned alongside the structure.
+pub fn assembly_structure(
+    name: &str,
+    records: &[AtomRecord],
+    parts: &[AssemblyPart],
+    radii: &RadiusTable,
+) -> std::result::Result<(Structure, Vec<Molecule>), String> {
+    // Group the atoms by their chain, keeping the order of chains in the file
+    let mut chains: Vec<(&str, Vec<AtomRecord>)> = Vec::new();
+    let mut chain_indices: HashMap<&str, usize> = HashMap::new();
+    for record in records {
+        let index = *chain_indices
+            .entry(record.label_chain.as_str())
+            .or_insert_with(|| {
+                chains.push((record.label_chain.as_str(), Vec::new()));
+                chains.len() - 1
+            });
+        chains[index].1.push(record.clone());
+    }
+
+    let mut structure = Structure::new(HashMap::new());
+    let mut molecules = Vec::new();
+
+    for part in parts {
+        for chain in part.chains.iter() {
+            let chain_records = match chain_indices.get(chain.as_str()) {
+                Some(index) => &chains[*index].1,
+                None => continue,
+            };
+            let molecule_name = format!("{}_{}", name, chain);
+
+            // Molecules are centered, so their instances have to be moved back to the original position of the chain
+            let chain_box = bounding_box(&record_atoms(chain_records, radii));
+            let center = translation(&((chain_box.min + chain_box.max) * 0.5));
+
+            if !structure.molecules.contains_key(&molecule_name) {
+                molecules.push(molecule_from_records(&molecule_name, chain_records, radii));
+            }
+
+            structure
+                .molecules
+                .entry(molecule_name)
+                .or_default()
+                .extend(part.operators.iter().map(|operator| operator * center));
+        }
+    }
+
+    if molecules.is_empty() {
+        return Err("no chains of the assembly have atoms".to_string());
+    }
+
+    Ok((structure, molecules))
+}
+
+/// Reads a PDB or mmCIF file, chosen by the `.cif` extension, and expands its biological assembly.
+/// `assembly_id` defaults to the first assembly of the file.
+pub fn read_assembly<P: AsRef<Path>>(
+    path: P,
+    assembly_id: Option<&str>,
+    radii: &RadiusTable,
+) -> Result<(Structure, Vec<Molecule>)> {
+    let name = path
+        .as_ref()
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_ascii_uppercase())
+        .unwrap_or_default();
+
+    let (records, parts) = if path.as_ref().extension().map_or(false, |e| e == "cif") {
+        let block = mmcif::read(&path)?;
+        let records = mmcif::atom_records(&block).map_err(|e| Error::parse(&path, 0, e))?;
+        let parts = cif_assembly(&block, assembly_id).map_err(|e| Error::parse(&path, 0, e))?;
+
+        (records, parts)
+    } else {
+        let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+        let records =
+            pdb::parse_atom_records(&text).map_err(|(line, e)| Error::parse(&path, line, e))?;
+        let parts = pdb_assembly(&text, assembly_id.unwrap_or("1"))
+            .map_err(|e| Error::parse(&path, 0, e))?;
+
+        (records, parts)
+    };
+
+    if records.is_empty() {
+        return Err(Error::empty_molecule(&path));
+    }
+
+    assembly_structure(&name, &records, &parts, radii).map_err(|e| Error::parse(&path, 0, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec4;
+
+    fn ids(range: std::ops::RangeInclusive<u32>) -> Vec<String> {
+        range.map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn oper_expressions_expand() {
+        assert_eq!(parse_oper_expression("1").unwrap(), vec![ids(1..=1)]);
+        assert_eq!(
+            parse_oper_expression("1,2,5").unwrap(),
+            vec![vec!["1".to_string(), "2".to_string(), "5".to_string()]]
+        );
+        assert_eq!(parse_oper_expression("(1-60)").unwrap(), vec![ids(1..=60)]);
+        assert_eq!(
+            parse_oper_expression("(1-5)(6-7)").unwrap(),
+            vec![ids(1..=5), ids(6..=7)]
+        );
+    }
+
+    #[test]
+    fn invalid_oper_expressions_are_rejected() {
+        for expression in ["", "()", " , ", "(,)"].iter() {
+            assert!(
+                parse_oper_expression(expression).is_err(),
+                "{:?}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn missing_operator_values_are_rejected() {
+        let column = vec!["1.0".to_string()];
+
+        assert_eq!(parse_operator_value(&column, 0), Ok(1.0));
+        assert!(parse_operator_value(&column, 1).is_err());
+    }
+
+    #[test]
+    fn cif_groups_are_multiplied() {
+        // Operator n translates by n along x, except operators 6 and 7 which translate along y
+        let mut text = "data_x
+loop_
+_pdbx_struct_oper_list.id
+_pdbx_struct_oper_list.matrix[1][1]
+_pdbx_struct_oper_list.matrix[1][2]
+_pdbx_struct_oper_list.matrix[1][3]
+_pdbx_struct_oper_list.vector[1]
+_pdbx_struct_oper_list.matrix[2][1]
+_pdbx_struct_oper_list.matrix[2][2]
+_pdbx_struct_oper_list.matrix[2][3]
+_pdbx_struct_oper_list.vector[2]
+_pdbx_struct_oper_list.matrix[3][1]
+_pdbx_struct_oper_list.matrix[3][2]
+_pdbx_struct_oper_list.matrix[3][3]
+_pdbx_struct_oper_list.vector[3]
+"
+        .to_string();
+        for id in 1..=7 {
+            let (x, y) = if id <= 5 { (id, 0) } else { (0, id) };
+            text += &format!("{} 1 0 0 {} 0 1 0 {} 0 0 1 0\n", id, x, y);
+        }
+        text += "_pdbx_struct_assembly_gen.assembly_id 1
+_pdbx_struct_assembly_gen.oper_expression '(1-5)(6-7)'
+_pdbx_struct_assembly_gen.asym_id_list A,B
+";
+
+        let parts = cif_assembly(&mmcif::parse(&text).unwrap(), None).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].chains, ["A", "B"]);
+        assert_eq!(parts[0].operators.len(), 10);
+        assert_eq!(
+            parts[0].operators[1] * vec4(0.0, 0.0, 0.0, 1.0),
+            vec4(1.0, 7.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            parts[0].operators[9] * vec4(0.0, 0.0, 0.0, 1.0),
+            vec4(5.0, 7.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn pdb_biomt_with_continued_chains() {
+        let text = "\
+REMARK 350 BIOMOLECULE: 1
+REMARK 350 APPLY THE FOLLOWING TO CHAINS: A, B,
+REMARK 350                    AND CHAINS: C
+REMARK 350   BIOMT1   1  1.000000  0.000000  0.000000        0.00000
+REMARK 350   BIOMT2   1  0.000000  1.000000  0.000000        0.00000
+REMARK 350   BIOMT3   1  0.000000  0.000000  1.000000        0.00000
+REMARK 350   BIOMT1   2 -1.000000  0.000000  0.000000       10.00000
+REMARK 350   BIOMT2   2  0.000000 -1.000000  0.000000       20.00000
+REMARK 350   BIOMT3   2  0.000000  0.000000  1.000000       -5.00000
+REMARK 350 BIOMOLECULE: 2
+REMARK 350 APPLY THE FOLLOWING TO CHAINS: D
+REMARK 350   BIOMT1   1  1.000000  0.000000  0.000000        0.00000
+REMARK 350   BIOMT2   1  0.000000  1.000000  0.000000        0.00000
+REMARK 350   BIOMT3   1  0.000000  0.000000  1.000000        0.00000
+ATOM      1  N   GLY A   1       1.000   2.000   3.000  1.00 10.00           N
+";
+
+        let parts = pdb_assembly(text, "1").unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].chains, ["A", "B", "C"]);
+        assert_eq!(parts[0].operators.len(), 2);
+        assert_eq!(parts[0].operators[0], Mat4::identity());
+        assert_eq!(
+            parts[0].operators[1] * vec4(1.0, 2.0, 3.0, 1.0),
+            vec4(9.0, 18.0, -2.0, 1.0)
+        );
+
+        assert_eq!(pdb_assembly(text, "2").unwrap()[0].chains, ["D"]);
+        assert!(pdb_assembly(text, "3").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use crate::molecule::{AtomAttributes, Molecule, MoleculeLod};
+use crate::radii::RadiusTable;
+use crate::{bounding_box, center_atoms};
+
+use nalgebra_glm::{vec4, Vec3, Vec4};
+
+/// Atom as read from a coordinate file, with its chemical identity and position in the chain.
+#[derive(Clone, Debug)]
+pub struct AtomRecord {
+    /// Atom name, e.g. `CA`.
+    pub name: String,
+
+    /// Element symbol, e.g. `C` or `FE`.
+    pub element: String,
+
+    /// Residue name, e.g. `ALA` or `HEM`.
+    pub residue_name: String,
+
+    /// Author residue sequence number.
+    pub residue_id: i32,
+
+    /// Author chain identifier.
+    pub chain: String,
+
+    /// Chain identifier assigned by the PDB. Same as `chain` for formats that have only one.
+    pub label_chain: String,
+
+    pub position: Vec3,
+
+    pub b_factor: f32,
+}
+
+/// Atoms of the records as spheres, in their original position. Atoms without a radius in `radii` are left out.
+pub fn record_atoms(records: &[AtomRecord], radii: &RadiusTable) -> Vec<Vec4> {
+    records
+        .iter()
+        .filter_map(|record| {
+            radii.radius(record).map(|radius| {
+                vec4(
+                    record.position.x,
+                    record.position.y,
+                    record.position.z,
+                    radius,
+                )
+            })
+        })
+        .collect()
+}
+
+impl AtomRecord {
+    pub fn attributes(&self) -> AtomAttributes {
+        AtomAttributes {
+            name: self.name.clone(),
+            element: self.element.clone(),
+            chain: self.chain.clone(),
+            residue_name: self.residue_name.clone(),
+            residue_id: self.residue_id,
+            b_factor: self.b_factor,
+        }
+    }
+}
+
+/// Creates a centered molecule with a single LOD, including attributes of atoms, from atom records.
+pub fn molecule_from_records(name: &str, records: &[AtomRecord], radii: &RadiusTable) -> Molecule {
+    let atoms = center_atoms(record_atoms(records, radii));
+    let attributes = records
+        .iter()
+        .filter(|record| radii.radius(record).is_some())
+        .map(|record| record.attributes())
+        .collect();
+
+    Molecule {
+        name: name.to_string(),
+        bounding_box: bounding_box(&atoms),
+        lods: vec![MoleculeLod::new(atoms, 0.0).with_attributes(attributes)],
+    }
+}
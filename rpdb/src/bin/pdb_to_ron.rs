@@ -1,175 +1,354 @@
 use nalgebra_glm::*;
+use rayon::prelude::*;
+use rpdb::args::{fail, Args};
 use rpdb::lod::*;
 use rpdb::*;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{prelude::*, BufReader};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const USAGE: &str = "Usage: pdb_to_ron [options] <molecule or recipe> [output]
+
+Converts a .pdb or .cif molecule, or a .txt or cellPACK .json recipe and the molecules it uses,
+to RON files next to the input unless an output file is given.
+
+Options:
+  --assembly <id>             write the biological assembly with the given id as a structure
+  --radii <scheme>            default, bondi, alvarez or united-atom (default default)
+  --radii-override <file>     RON map of elements to radii replacing those of the scheme
+  --reducer <name>            kmeans, octree, agglomerative or residue (default kmeans)
+  --seed <u64>                seed of randomized reducers
+  --lod-error <pixels>        switch LODs by their projected error instead of their area
+  --recipe-config <file>      RON selection of the molecules and instances of a recipe
+  --include <name,...>        convert only these molecules of a recipe
+  --exclude <name,...>        leave these molecules of a recipe out
+  --clip-box <x,y,z,x,y,z>    keep the instances of a recipe inside the box
+  --clip-sphere <x,y,z,r>     keep the instances of a recipe inside the sphere
+  --clip-to <name>            keep the instances of a recipe inside the bounds of a molecule
+  --force                     convert all molecules of a recipe, not only changed ones";
+
+/// Writes `value` as RON to `path`, exiting with a failure code when it can not be written.
+fn write_ron<T: ToRon>(value: &T, path: &Path) {
+    value.try_to_ron(path).unwrap_or_else(|e| fail(e));
+}
+
+/// Takes the comma separated list of `count` numbers given to the option `name`.
+fn parse_numbers(args: &mut Args, name: &str, count: usize) -> Option<Vec<f32>> {
+    let numbers: Vec<f32> = args.parse_list_option(name)?;
+
+    if numbers.len() != count {
+        args.usage_error(&format!(
+            "{} expects {} comma separated numbers",
+            name, count
+        ));
+    }
+
+    Some(numbers)
+}
+
+/// Selection of the molecules and instances of a recipe, read from `--recipe-config <file>` and extended
+/// or overridden by the other recipe options.
+fn recipe_config(args: &mut Args) -> recipe::RecipeConfig {
+    let mut config = args
+        .option("--recipe-config")
+        .map(|path| recipe::RecipeConfig::try_from_ron(path).unwrap_or_else(|e| fail(e)))
+        .unwrap_or_default();
+
+    let names = |value: String| {
+        value
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect::<Vec<_>>()
+    };
+    if let Some(include) = args.option("--include") {
+        config.include.extend(names(include));
+    }
+    if let Some(exclude) = args.option("--exclude") {
+        config.exclude.extend(names(exclude));
+    }
+
+    if let Some(n) = parse_numbers(args, "--clip-box", 6) {
+        config.clip = Some(recipe::ClipVolume::Box {
+            min: vec3(n[0], n[1], n[2]),
+            max: vec3(n[3], n[4], n[5]),
+        });
+    }
+    if let Some(n) = parse_numbers(args, "--clip-sphere", 4) {
+        config.clip = Some(recipe::ClipVolume::Sphere {
+            center: vec3(n[0], n[1], n[2]),
+            radius: n[3],
+        });
+    }
+    if let Some(reference) = args.option("--clip-to") {
+        config.clip_to = Some(reference);
+    }
+
+    config
+}
+
+/// Outcome of converting a molecule referenced by a recipe.
+enum Conversion {
+    Converted { atoms: usize, lods: usize },
+    UpToDate,
+    Failed(String),
+}
+
+impl std::fmt::Display for Conversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Conversion::Converted { atoms, lods } => write!(f, "{} atoms, {} LODs", atoms, lods),
+            Conversion::UpToDate => write!(f, "up to date"),
+            Conversion::Failed(message) => write!(f, "failed, {}", message),
+        }
+    }
+}
+
+/// Whether `target` exists and was modified after `source`.
+fn is_up_to_date(source: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    match (modified(source), modified(target)) {
+        (Some(source), Some(target)) => target >= source,
+        _ => false,
+    }
+}
+
+fn convert_molecule<F: Fn(&mut molecule::Molecule)>(
+    pdb_path: &Path,
+    ron_path: &Path,
+    radii: &radii::RadiusTable,
+    create_lods: &F,
+) -> Conversion {
+    let result =
+        molecule::Molecule::try_from_pdb_with_radii(pdb_path, radii).and_then(|mut molecule| {
+            create_lods(&mut molecule);
+            molecule.try_to_ron(ron_path)?;
+
+            Ok(Conversion::Converted {
+                atoms: molecule.lods()[0].atoms().len(),
+                lods: molecule.lods().len(),
+            })
+        });
+
+    result.unwrap_or_else(|e| Conversion::Failed(e.to_string()))
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let in_file_path: &str = &args[1];
+    let mut args = Args::new(USAGE);
+    let assembly = args.option("--assembly");
+
+    // Recipes
+    let recipe_config = recipe_config(&mut args);
+
+    // Recipes convert all molecules, not only those changed since their last conversion
+    let force = args.flag("--force");
+
+    // Radii of atoms
+    let scheme = args
+        .parse_option::<radii::RadiusScheme>("--radii")
+        .unwrap_or(radii::RadiusScheme::Default);
+    let mut radii = radii::RadiusTable::new(scheme);
+    if let Some(overrides_path) = args.option("--radii-override") {
+        radii
+            .load_overrides(overrides_path)
+            .unwrap_or_else(|e| fail(e));
+    }
 
-    if in_file_path.ends_with(".pdb") {
+    // Simplification of molecules into LODs
+    let seed = args.parse_option::<u64>("--seed");
+    let reducer = kmeans::reducer_from_name(
+        &args
+            .option("--reducer")
+            .unwrap_or_else(|| "kmeans".to_string()),
+        seed,
+    )
+    .unwrap_or_else(|e| args.usage_error(&e));
+
+    // LODs switch by the projected area of their largest sphere, or by their projected error with `--lod-error <pixels>`
+    let lod_error = args.parse_option::<f32>("--lod-error");
+    let mut lod_config = LodConfig::default();
+    if let Some(pixels) = lod_error {
+        lod_config.error_threshold = pixels;
+    }
+    let create_lods = |molecule: &mut molecule::Molecule| {
+        if lod_error.is_some() {
+            molecule.create_lods_by_error(reducer.as_ref(), &lod_config);
+        } else {
+            molecule.create_lods(reducer.as_ref(), &lod_config);
+        }
+    };
+
+    let positional = args.positional();
+    if positional.is_empty() || positional.len() > 2 {
+        args.usage_error("expected an input and an optional output path");
+    }
+    let in_file_path: &str = &positional[0];
+    let output = positional.get(1).map(std::path::PathBuf::from);
+
+    if let Some(assembly_id) = assembly {
         let name = std::path::Path::new(in_file_path)
-            .file_name()
+            .file_stem()
             .unwrap()
             .to_str()
             .unwrap()
-            .trim_end_matches(".pdb")
             .to_ascii_uppercase();
 
-        let molecule = molecule::Molecule::from_pdb(in_file_path);
-        println!("Number of atoms: {}", molecule.lods()[0].atoms().len());
+        let (structure, molecules) =
+            assembly::read_assembly(in_file_path, Some(&assembly_id), &radii)
+                .unwrap_or_else(|e| fail(e));
 
-        // TODO: Optionally generate LODs
+        let out_file_path = output
+            .unwrap_or_else(|| std::path::Path::new(in_file_path).with_file_name(name + ".ron"));
 
-        let out_file_path = if args.len() >= 3 {
-            args[2].clone()
+        for mut molecule in molecules {
+            println!(
+                "Converting chain molecule: {} ({} atoms, {} instances)",
+                molecule.name(),
+                molecule.lods()[0].atoms().len(),
+                structure.molecules[molecule.name()].len()
+            );
+
+            create_lods(&mut molecule);
+            write_ron(
+                &molecule,
+                &out_file_path.with_file_name(molecule.name().to_owned() + ".ron"),
+            );
+        }
+
+        println!("Writing structure to: {}", out_file_path.display());
+        write_ron(&structure, &out_file_path);
+    } else if in_file_path.ends_with(".pdb") || in_file_path.ends_with(".cif") {
+        let name = std::path::Path::new(in_file_path)
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_ascii_uppercase();
+
+        let mut molecule = if in_file_path.ends_with(".cif") {
+            molecule::Molecule::try_from_cif_with_radii(in_file_path, &radii)
         } else {
-            std::path::Path::new(in_file_path)
-                .parent()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_owned()
-                + "\\"
-                + &name
-                + ".ron"
-        };
-        println!("Writing molecule to: {}", out_file_path);
+            molecule::Molecule::try_from_pdb_with_radii(in_file_path, &radii)
+        }
+        .unwrap_or_else(|e| fail(e));
+        println!("Number of atoms: {}", molecule.lods()[0].atoms().len());
+
+        create_lods(&mut molecule);
+        println!("Number of LODs: {}", molecule.lods().len());
+
+        let out_file_path = output
+            .unwrap_or_else(|| std::path::Path::new(in_file_path).with_file_name(name + ".ron"));
+        println!("Writing molecule to: {}", out_file_path.display());
 
-        molecule.to_ron(out_file_path);
-    } else if in_file_path.ends_with(".txt") {
+        write_ron(&molecule, &out_file_path);
+    } else if in_file_path.ends_with(".txt") || in_file_path.ends_with(".json") {
         let in_file_path = std::path::Path::new(in_file_path);
         let name = in_file_path
-            .file_name()
+            .file_stem()
             .unwrap()
             .to_str()
             .unwrap()
-            .trim_end_matches(".txt")
             .to_ascii_uppercase();
 
-        let file = File::open(in_file_path).expect("Could not open file.");
-        let file_reader = BufReader::new(file);
-        let mut file_lines = file_reader.lines();
-
-        let mut scale = 1.0f32;
-        // if let Some(line) = file_lines.next() {
-        //     if let Ok(line) = line {
-        //         scale = 1.0f32 / line.parse::<f32>().unwrap();
-        //     }
-        // }
-
-        let mut molecules: HashMap<String, Vec<Mat4>> = HashMap::new();
-        let mut min_position = vec3(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
-        let mut max_position = vec3(
-            std::f32::NEG_INFINITY,
-            std::f32::NEG_INFINITY,
-            std::f32::NEG_INFINITY,
-        );
-        for line in file_lines {
-            if let Ok(line) = line {
-                let parts: Vec<&str> = line.split(' ').collect();
-
-                if parts.len() >= 8 {
-                    let molecule_name = parts[0];
-
-                    if molecule_name == "hiv1_sp1_hack_0_1_0"
-                    || molecule_name == "1esx"
-                    || molecule_name == "nefstef_1"
-                    || molecule_name == "1ak4a"
-                    || molecule_name == "modbase_vif"
-                    || molecule_name == "hiv1_p6_vpr"
-                    || molecule_name == "hiv1_sp2_hack_0_1_0"
-                    || molecule_name == "hiv1_p6_swissmod_0_1_0"
-                    || molecule_name == "1ak4fitto1vu4hex_manu"
-                    || molecule_name == "7hvp" {
-                        continue;
-                    }
-
-                    let molecule_position = vec3(
-                        parts[1].parse::<f32>().unwrap(),
-                        parts[2].parse::<f32>().unwrap(),
-                        parts[3].parse::<f32>().unwrap(),
-                    );
-                    let molecule_quaternion = quat(
-                        parts[5].parse::<f32>().unwrap(),
-                        parts[6].parse::<f32>().unwrap(),
-                        parts[7].parse::<f32>().unwrap(),
-                        parts[4].parse::<f32>().unwrap(),
-                    );
-
-                    if molecule_name == "3j3q_1vu4_a_biomt" {
-                        min_position = min2(&min_position, &molecule_position);
-                        max_position = max2(&max_position, &molecule_position);
-                    }
-
-                    let translation = translation(&(scale * molecule_position));
-                    let rotation = quat_to_mat4(&molecule_quaternion);
-                    let model_matrix = translation * rotation;
-
-                    if !molecules.contains_key(molecule_name) {
-                        println!("Converting molecule: {}", molecule_name);
-
-                        // Load existing molecule
-                        let mut molecule = molecule::Molecule::from_pdb(
-                            in_file_path.with_file_name(molecule_name.to_lowercase() + ".pdb"),
-                        );
-
-                        // Create its LODs
-                        molecule.create_lods();
-
-                        // Write it to .ron file next the main file
-                        molecule
-                            .to_ron(in_file_path.with_file_name(molecule_name.to_owned() + ".ron"));
-
-                        // Insert the molecule into the HashMap of molecules
-                        molecules.insert(molecule_name.to_string(), vec![]);
-                    }
-
-                    if let Some(v) = molecules.get_mut(&molecule_name.to_string()) {
-                        v.push(model_matrix);
-                    }
-                }
-            }
-        }
+        // Structure of the recipe and the PDB files of its molecules
+        let (mut structure, ingredients) = if in_file_path.extension().unwrap() == "json" {
+            recipe::read_cellpack_json(in_file_path).unwrap_or_else(|e| fail(e))
+        } else {
+            let molecules = recipe::read_recipe(in_file_path).unwrap_or_else(|e| fail(e));
+            let ingredients = molecules
+                .keys()
+                .map(|name| recipe::Ingredient {
+                    name: name.clone(),
+                    pdb: in_file_path.with_file_name(name.to_lowercase() + ".pdb"),
+                })
+                .collect();
 
-        // Center the molecules (+their bounding box)
-        // let bb_center: Vec3 = (max_position + min_position) * 0.5;
-        // for (_, model_matrices) in molecules.iter_mut() {
-        //     for model_matrix in model_matrices.iter_mut() {
-        //         *model_matrix = translation(&vec3(-bb_center.x, -bb_center.y, -bb_center.z)) * (*model_matrix);
-        //     }
-        // }
+            (structure::Structure::new(molecules), ingredients)
+        };
+        recipe_config
+            .apply(&mut structure)
+            .unwrap_or_else(|e| fail(e));
 
-        for (_, model_matrices) in molecules.iter_mut() {
-            model_matrices.retain(|&m| {
-                let translation = m.column(3).xyz();
+        let molecules = structure.flatten();
+        let mut ingredients: Vec<recipe::Ingredient> = ingredients
+            .into_iter()
+            .filter(|ingredient| molecules.contains_key(&ingredient.name))
+            .collect();
+        ingredients.sort_by(|a, b| a.name.cmp(&b.name));
 
-                translation < max_position && translation > min_position
-            });
-        }
+        // Convert the referenced molecules in parallel, skipping those converted since their PDB file changed
+        let start = Instant::now();
+        let finished = AtomicUsize::new(0);
+        let conversions: Vec<Conversion> = ingredients
+            .par_iter()
+            .map(|ingredient| {
+                let ron_path = in_file_path.with_file_name(ingredient.name.to_owned() + ".ron");
 
-        let structure = structure::Structure { molecules };
+                let conversion = if !force && is_up_to_date(&ingredient.pdb, &ron_path) {
+                    Conversion::UpToDate
+                } else {
+                    convert_molecule(&ingredient.pdb, &ron_path, &radii, &create_lods)
+                };
 
-        let structure_string =
-            ron::ser::to_string(&structure).expect("Could not convert the structure to RON.");
+                let finished = finished.fetch_add(1, Ordering::SeqCst) + 1;
+                println!(
+                    "[{}/{}] {}: {}",
+                    finished,
+                    ingredients.len(),
+                    ingredient.name,
+                    conversion
+                );
 
-        let out_file_path = if args.len() >= 3 {
-            args[2].clone()
-        } else {
-            std::path::Path::new(in_file_path)
-                .parent()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_owned()
-                + "\\"
-                + &name
-                + ".ron"
-        };
-        std::fs::write(&out_file_path, structure_string).expect("Could not write the structure.");
+                conversion
+            })
+            .collect();
+
+        // Summary
+        let converted = conversions
+            .iter()
+            .filter(|c| matches!(c, Conversion::Converted { .. }))
+            .count();
+        let up_to_date = conversions
+            .iter()
+            .filter(|c| matches!(c, Conversion::UpToDate))
+            .count();
+        let atoms: usize = conversions
+            .iter()
+            .map(|c| match c {
+                Conversion::Converted { atoms, .. } => *atoms,
+                _ => 0,
+            })
+            .sum();
+        println!(
+            "Converted {} molecules ({} atoms), {} up to date, {} failed in {:.1?}",
+            converted,
+            atoms,
+            up_to_date,
+            ingredients.len() - converted - up_to_date,
+            start.elapsed()
+        );
+
+        // Failed molecules are left out of the structure
+        let mut failed = Vec::new();
+        for (ingredient, conversion) in ingredients.iter().zip(conversions.iter()) {
+            if let Conversion::Failed(message) = conversion {
+                println!("  Failed {}: {}", ingredient.name, message);
+                failed.push(ingredient.name.as_str());
+            }
+        }
+        structure.retain_instances(|name, _| !failed.contains(&name));
+
+        let out_file_path = output.unwrap_or_else(|| in_file_path.with_file_name(name + ".ron"));
+        println!("Writing structure to: {}", out_file_path.display());
+        write_ron(&structure, &out_file_path);
+
+        if !failed.is_empty() {
+            std::process::exit(1);
+        }
     } else {
-        println!("Unknown format.");
+        args.usage_error(&format!(
+            "unknown format of {}, expected .pdb, .cif, .txt or .json",
+            in_file_path
+        ));
     }
 }
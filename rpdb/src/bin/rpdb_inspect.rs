@@ -0,0 +1,77 @@
+use rpdb::args::Args;
+use rpdb::inspect::{inspect, Report};
+
+const USAGE: &str = "Usage: rpdb_inspect [--json] <structure or molecule file>...
+
+Validates molecule and structure files and prints their statistics and issues, as JSON with --json.";
+
+fn print_report(report: &Report) {
+    println!("{} ({})", report.path, report.kind);
+
+    for molecule in report.molecules.iter() {
+        println!(
+            "  {}: {} instances, {} atoms",
+            molecule.name, molecule.instances, molecule.atoms_total
+        );
+        for (index, lod) in molecule.lods.iter().enumerate() {
+            print!(
+                "    LOD {}: {} atoms, breakpoint {}, max radius {}",
+                index, lod.atoms, lod.breakpoint, lod.max_radius
+            );
+            match lod.error {
+                Some(error) => println!(", error {}", error),
+                None => println!(),
+            }
+        }
+    }
+
+    println!(
+        "  Total: {} molecules, {} instances, {} atoms",
+        report.molecules.len(),
+        report.instances_total,
+        report.atoms_total
+    );
+    if let Some(bounding_box) = report.bounding_box {
+        println!(
+            "  Bounding box: [{}, {}, {}] - [{}, {}, {}]",
+            bounding_box.min.x,
+            bounding_box.min.y,
+            bounding_box.min.z,
+            bounding_box.max.x,
+            bounding_box.max.y,
+            bounding_box.max.z
+        );
+    }
+
+    for issue in report.issues.iter() {
+        match &issue.molecule {
+            Some(molecule) => println!("  {:?} in {}: {}", issue.kind, molecule, issue.message),
+            None => println!("  {:?}: {}", issue.kind, issue.message),
+        }
+    }
+}
+
+fn main() {
+    let mut args = Args::new(USAGE);
+
+    let json = args.flag("--json");
+
+    let paths = args.positional();
+    if paths.is_empty() {
+        args.usage_error("expected at least one file");
+    }
+
+    let reports: Vec<Report> = paths.iter().map(inspect).collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+    } else {
+        for report in reports.iter() {
+            print_report(report);
+        }
+    }
+
+    if !reports.iter().all(|report| report.is_valid()) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,775 @@
+//! Compact little-endian binary container for molecules and structures.
+//!
+//! Molecule layout (all integers `u32`, all reals `f32`):
+//!
+//! | Bytes                | Content                                              |
+//! |----------------------|------------------------------------------------------|
+//! | 0..4                 | Magic `RPDM`                                         |
+//! | 4..8                 | Format version                                       |
+//! | 8..12                | Number of LODs                                       |
+//! | 12..16               | Total number of atoms across all LODs                |
+//! | 16..40               | Bounding box (min xyz, max xyz)                      |
+//! | 40..44               | Length of the name in bytes                          |
+//! | 44..48               | Reserved                                             |
+//! | 48..                 | UTF-8 name, zero padded to a multiple of 16 bytes    |
+//! | ..                   | LOD table, 16 bytes per LOD: breakpoint, max radius, first atom, end atom |
+//! | ..                   | Atoms, 16 bytes per atom: x, y, z, radius            |
+//! | ..                   | Since version 2, per LOD its flags followed by the optional sections they mark |
+//!
+//! LOD flags and optional sections, in this order:
+//!
+//! | Flag | Section                                                                                           |
+//! |------|---------------------------------------------------------------------------------------------------|
+//! | 1    | Attributes of each atom of the LOD: name, element, chain, residue name, residue id, B-factor       |
+//! | 2    | Representatives: count, per atom of LOD 0 the index of the sphere of this LOD that represents it   |
+//! | 4    | Geometric error of the LOD                                                                        |
+//!
+//! Strings in the optional sections are stored as their length in bytes followed by UTF-8 bytes, without padding.
+//!
+//! Structure layout:
+//!
+//! | Bytes                | Content                                              |
+//! |----------------------|------------------------------------------------------|
+//! | 0..4                 | Magic `RPDS`                                         |
+//! | 4..8                 | Format version                                       |
+//! | 8..12                | Number of molecule types                             |
+//! | 12..16               | Total number of instances                            |
+//! | 16..                 | Molecule table: name length, UTF-8 name, first instance, end instance |
+//! | ..                   | Zero padding to a multiple of 16 bytes               |
+//! | ..                   | Instances, 64 bytes per instance: column-major `Mat4` |
+//!
+//! Atoms and instances always start at an offset aligned to 16 bytes so that they can be viewed in place.
+use crate::error::{Error, Result};
+use crate::molecule::{AtomAttributes, Molecule, MoleculeLod};
+use crate::structure::Structure;
+use crate::BoundingBox;
+
+use nalgebra_glm::{vec3, Mat4, Vec4};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const MOLECULE_MAGIC: [u8; 4] = *b"RPDM";
+pub const STRUCTURE_MAGIC: [u8; 4] = *b"RPDS";
+pub const VERSION: u32 = 2;
+
+/// Flags of the optional LOD sections.
+const LOD_ATTRIBUTES: u32 = 1;
+const LOD_REPRESENTATIVES: u32 = 2;
+const LOD_ERROR: u32 = 4;
+
+/// File extension used for binary molecules and structures.
+pub const EXTENSION: &str = "rpdb";
+
+const MOLECULE_HEADER_SIZE: usize = 48;
+const LOD_ENTRY_SIZE: usize = 16;
+const ATOM_SIZE: usize = 16;
+const INSTANCE_SIZE: usize = 64;
+
+/// Checks whether the data starts with magic bytes of binary molecule or structure.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MOLECULE_MAGIC) || bytes.starts_with(&STRUCTURE_MAGIC)
+}
+
+/// Checks whether the file at `path` is a binary molecule or structure.
+pub fn is_binary_file<P: AsRef<Path>>(path: P) -> Result<bool> {
+    use std::io::Read;
+
+    let file = std::fs::File::open(&path).map_err(|e| Error::io(&path, e))?;
+    let mut magic = Vec::with_capacity(4);
+    file.take(4)
+        .read_to_end(&mut magic)
+        .map_err(|e| Error::io(&path, e))?;
+
+    Ok(is_binary(&magic))
+}
+
+fn padded(len: usize) -> usize {
+    (len + 15) & !15
+}
+
+/// Entry of the LOD table of a binary molecule.
+#[derive(Clone, Debug)]
+pub struct LodEntry {
+    pub breakpoint: f32,
+    pub max_radius: f32,
+    /// Range into the atom array, in atoms.
+    pub atoms: std::ops::Range<usize>,
+}
+
+/// Parsed header of a binary molecule. Atoms are not read, only located.
+#[derive(Clone, Debug)]
+pub struct MoleculeHeader {
+    pub version: u32,
+    pub name: String,
+    pub bounding_box: BoundingBox,
+    pub lods: Vec<LodEntry>,
+
+    /// Byte offset of the atom array from the start of the file.
+    pub atoms_offset: usize,
+
+    /// Total number of atoms across all LODs.
+    pub atoms_count: usize,
+}
+
+/// Little-endian reader over a byte slice with bounds checking.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], String> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("unexpected end of data at byte {}", self.offset))?;
+        let data = &self.bytes[self.offset..end];
+        self.offset = end;
+
+        Ok(data)
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        let mut data = [0u8; 4];
+        data.copy_from_slice(self.take(4)?);
+
+        Ok(u32::from_le_bytes(data))
+    }
+
+    /// Count of following items, each at least `item_size` bytes long. Counts that can not fit into the remaining
+    /// data are rejected before anything is allocated for them.
+    fn count(&mut self, item_size: usize) -> std::result::Result<usize, String> {
+        let count = self.u32()? as usize;
+        let remaining = self.bytes.len() - self.offset;
+        let size = count.checked_mul(item_size);
+        if size.filter(|size| *size <= remaining).is_none() {
+            return Err(format!(
+                "count {} at byte {} exceeds the remaining {} bytes",
+                count,
+                self.offset - 4,
+                remaining
+            ));
+        }
+
+        Ok(count)
+    }
+
+    fn f32(&mut self) -> std::result::Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn i32(&mut self) -> std::result::Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn string(&mut self, len: usize) -> std::result::Result<String, String> {
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    /// String prefixed by its length.
+    fn sized_string(&mut self) -> std::result::Result<String, String> {
+        let len = self.u32()? as usize;
+        self.string(len)
+    }
+
+    fn align(&mut self) -> std::result::Result<(), String> {
+        let padding = padded(self.offset) - self.offset;
+        self.take(padding).map(|_| ())
+    }
+}
+
+/// Checks the magic bytes and returns the format version. All versions up to `VERSION` are read.
+fn check_magic(reader: &mut Reader, magic: [u8; 4]) -> std::result::Result<u32, String> {
+    let found = reader.take(4)?;
+    if found != magic {
+        return Err(format!(
+            "expected magic {:?}, found {:?}",
+            String::from_utf8_lossy(&magic),
+            String::from_utf8_lossy(found)
+        ));
+    }
+
+    let version = reader.u32()?;
+    if version == 0 || version > VERSION {
+        return Err(format!("unsupported version {}", version));
+    }
+
+    Ok(version)
+}
+
+/// Parses the header and LOD table of a binary molecule.
+pub fn parse_molecule_header(bytes: &[u8]) -> std::result::Result<MoleculeHeader, String> {
+    let mut reader = Reader::new(bytes);
+    let version = check_magic(&mut reader, MOLECULE_MAGIC)?;
+
+    let lods_count = reader.count(LOD_ENTRY_SIZE)?;
+    let atoms_count = reader.count(ATOM_SIZE)?;
+    let min = vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+    let max = vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+    let name_len = reader.u32()? as usize;
+    let _reserved = reader.u32()?;
+    debug_assert_eq!(reader.offset, MOLECULE_HEADER_SIZE);
+
+    let name = reader.string(name_len)?;
+    reader.align()?;
+
+    let mut lods = Vec::with_capacity(lods_count);
+    for _ in 0..lods_count {
+        let breakpoint = reader.f32()?;
+        let max_radius = reader.f32()?;
+        let start = reader.u32()? as usize;
+        let end = reader.u32()? as usize;
+
+        if start > end || end > atoms_count {
+            return Err(format!(
+                "LOD range {}..{} is outside of {} atoms",
+                start, end, atoms_count
+            ));
+        }
+
+        lods.push(LodEntry {
+            breakpoint,
+            max_radius,
+            atoms: start..end,
+        });
+    }
+
+    let atoms_offset = reader.offset;
+    reader.take(atoms_count * ATOM_SIZE)?;
+
+    Ok(MoleculeHeader {
+        version,
+        name,
+        bounding_box: BoundingBox { min, max },
+        lods,
+        atoms_offset,
+        atoms_count,
+    })
+}
+
+/// Decodes a binary molecule.
+pub fn decode_molecule(bytes: &[u8]) -> std::result::Result<Molecule, String> {
+    let header = parse_molecule_header(bytes)?;
+
+    let mut reader = Reader::new(bytes);
+    reader.offset = header.atoms_offset;
+    let mut atoms = Vec::with_capacity(header.atoms_count);
+    for _ in 0..header.atoms_count {
+        atoms.push(Vec4::new(
+            reader.f32()?,
+            reader.f32()?,
+            reader.f32()?,
+            reader.f32()?,
+        ));
+    }
+
+    let lods = header
+        .lods
+        .iter()
+        .map(|lod| {
+            if lod.atoms.start == lod.atoms.end {
+                return Err("LOD without atoms".to_string());
+            }
+            let mut decoded = MoleculeLod::new(atoms[lod.atoms.clone()].to_vec(), lod.breakpoint);
+            if header.version >= 2 {
+                decoded = decode_lod_sections(&mut reader, decoded)?;
+            }
+
+            Ok(decoded)
+        })
+        .collect::<std::result::Result<Vec<_>, String>>()?;
+
+    Ok(Molecule {
+        name: header.name,
+        bounding_box: header.bounding_box,
+        lods,
+    })
+}
+
+/// Reads the flags and optional sections of a LOD following the atoms.
+fn decode_lod_sections(
+    reader: &mut Reader,
+    mut lod: MoleculeLod,
+) -> std::result::Result<MoleculeLod, String> {
+    let flags = reader.u32()?;
+    if flags & !(LOD_ATTRIBUTES | LOD_REPRESENTATIVES | LOD_ERROR) != 0 {
+        return Err(format!("unknown LOD flags {:#x}", flags));
+    }
+
+    if flags & LOD_ATTRIBUTES != 0 {
+        let mut attributes = Vec::with_capacity(lod.atoms().len());
+        for _ in 0..lod.atoms().len() {
+            attributes.push(AtomAttributes {
+                name: reader.sized_string()?,
+                element: reader.sized_string()?,
+                chain: reader.sized_string()?,
+                residue_name: reader.sized_string()?,
+                residue_id: reader.i32()?,
+                b_factor: reader.f32()?,
+            });
+        }
+        lod = lod.with_attributes(attributes);
+    }
+
+    if flags & LOD_REPRESENTATIVES != 0 {
+        let count = reader.u32()? as usize;
+        let spheres = lod.atoms().len();
+
+        // Bounded by the remaining data before allocating
+        let data = reader.take(
+            count
+                .checked_mul(4)
+                .ok_or("representatives count overflows")?,
+        )?;
+        let representatives: Vec<u32> = data
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        if let Some(sphere) = representatives.iter().find(|r| **r as usize >= spheres) {
+            return Err(format!(
+                "representative {} is outside of {} spheres",
+                sphere, spheres
+            ));
+        }
+        lod = lod.with_representatives(representatives);
+    }
+
+    if flags & LOD_ERROR != 0 {
+        lod = lod.with_error(reader.f32()?);
+    }
+
+    Ok(lod)
+}
+
+/// Writes the flags and optional sections of a LOD following the atoms.
+fn encode_lod_sections(bytes: &mut Vec<u8>, lod: &MoleculeLod) {
+    let attributes = lod.attributes();
+    let representatives = lod.representatives();
+
+    let mut flags = 0;
+    if attributes.is_some() {
+        flags |= LOD_ATTRIBUTES;
+    }
+    if representatives.is_some() {
+        flags |= LOD_REPRESENTATIVES;
+    }
+    if lod.error().is_some() {
+        flags |= LOD_ERROR;
+    }
+    put_u32(bytes, flags);
+
+    for attribute in attributes.unwrap_or(&[]) {
+        put_string(bytes, &attribute.name);
+        put_string(bytes, &attribute.element);
+        put_string(bytes, &attribute.chain);
+        put_string(bytes, &attribute.residue_name);
+        put_u32(bytes, attribute.residue_id as u32);
+        put_f32(bytes, attribute.b_factor);
+    }
+
+    if let Some(representatives) = representatives {
+        put_u32(bytes, representatives.len() as u32);
+        for representative in representatives {
+            put_u32(bytes, *representative);
+        }
+    }
+
+    if let Some(error) = lod.error() {
+        put_f32(bytes, error);
+    }
+}
+
+/// Encodes a molecule into the binary format.
+pub fn encode_molecule(molecule: &Molecule) -> Vec<u8> {
+    let atoms_count: usize = molecule.lods().iter().map(|lod| lod.atoms().len()).sum();
+    let name = molecule.name().as_bytes();
+
+    let mut bytes = Vec::with_capacity(
+        MOLECULE_HEADER_SIZE
+            + padded(name.len())
+            + molecule.lods().len() * LOD_ENTRY_SIZE
+            + atoms_count * ATOM_SIZE,
+    );
+
+    bytes.extend_from_slice(&MOLECULE_MAGIC);
+    put_u32(&mut bytes, VERSION);
+    put_u32(&mut bytes, molecule.lods().len() as u32);
+    put_u32(&mut bytes, atoms_count as u32);
+    let bounding_box = molecule.bounding_box();
+    for v in bounding_box.min.iter().chain(bounding_box.max.iter()) {
+        put_f32(&mut bytes, *v);
+    }
+    put_u32(&mut bytes, name.len() as u32);
+    put_u32(&mut bytes, 0);
+
+    bytes.extend_from_slice(name);
+    bytes.resize(padded(bytes.len()), 0);
+
+    let mut start = 0u32;
+    for lod in molecule.lods() {
+        let end = start + lod.atoms().len() as u32;
+        put_f32(&mut bytes, lod.breakpoint());
+        put_f32(&mut bytes, lod.max_radius());
+        put_u32(&mut bytes, start);
+        put_u32(&mut bytes, end);
+        start = end;
+    }
+
+    for lod in molecule.lods() {
+        for atom in lod.atoms() {
+            for v in atom.iter() {
+                put_f32(&mut bytes, *v);
+            }
+        }
+    }
+
+    for lod in molecule.lods() {
+        encode_lod_sections(&mut bytes, lod);
+    }
+
+    bytes
+}
+
+/// Decodes a binary structure.
+pub fn decode_structure(bytes: &[u8]) -> std::result::Result<Structure, String> {
+    let mut reader = Reader::new(bytes);
+    check_magic(&mut reader, STRUCTURE_MAGIC)?;
+
+    // Each molecule entry has at least its name length and instance range
+    let molecules_count = reader.count(12)?;
+    let instances_count = reader.count(INSTANCE_SIZE)?;
+
+    let mut table = Vec::with_capacity(molecules_count);
+    for _ in 0..molecules_count {
+        let name_len = reader.u32()? as usize;
+        let name = reader.string(name_len)?;
+        let start = reader.u32()? as usize;
+        let end = reader.u32()? as usize;
+
+        if start > end || end > instances_count {
+            return Err(format!(
+                "instance range {}..{} of {} is outside of {} instances",
+                start, end, name, instances_count
+            ));
+        }
+
+        table.push((name, start..end));
+    }
+    reader.align()?;
+
+    let mut instances = Vec::with_capacity(instances_count);
+    for _ in 0..instances_count {
+        let mut values = [0.0f32; 16];
+        for value in values.iter_mut() {
+            *value = reader.f32()?;
+        }
+        instances.push(Mat4::from_column_slice(&values));
+    }
+
+    let mut molecules = HashMap::with_capacity(molecules_count);
+    for (name, range) in table {
+        molecules.insert(name, instances[range].to_vec());
+    }
+
+    Ok(Structure::new(molecules))
+}
+
+/// Encodes a structure into the binary format. Molecule types are stored sorted by their name.
+/// Compartments are not part of the format, so structures with compartments are rejected rather than losing their
+/// hierarchy; flatten them first with `Structure::new(structure.flatten())` to store their instances.
+pub fn encode_structure(structure: &Structure) -> std::result::Result<Vec<u8>, String> {
+    if !structure.compartments.is_empty() {
+        return Err(format!(
+            "structure has {} compartments, which the binary format can not store",
+            structure.compartments.len()
+        ));
+    }
+
+    let molecules = &structure.molecules;
+    let mut names: Vec<&String> = molecules.keys().collect();
+    names.sort();
+
+    let instances_count: usize = molecules.values().map(|v| v.len()).sum();
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&STRUCTURE_MAGIC);
+    put_u32(&mut bytes, VERSION);
+    put_u32(&mut bytes, names.len() as u32);
+    put_u32(&mut bytes, instances_count as u32);
+
+    let mut start = 0u32;
+    for name in names.iter() {
+        let end = start + molecules[*name].len() as u32;
+        put_u32(&mut bytes, name.len() as u32);
+        bytes.extend_from_slice(name.as_bytes());
+        put_u32(&mut bytes, start);
+        put_u32(&mut bytes, end);
+        start = end;
+    }
+    bytes.resize(padded(bytes.len()), 0);
+
+    bytes.reserve(instances_count * INSTANCE_SIZE);
+    for name in names.iter() {
+        for instance in molecules[*name].iter() {
+            for v in instance.as_slice() {
+                put_f32(&mut bytes, *v);
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32(bytes: &mut Vec<u8>, value: f32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_string(bytes: &mut Vec<u8>, value: &str) {
+    put_u32(bytes, value.len() as u32);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+pub trait FromBinary: Sized {
+    fn try_from_binary<P: AsRef<Path>>(p: P) -> Result<Self>;
+
+    fn from_binary<P: AsRef<Path>>(p: P) -> Self {
+        Self::try_from_binary(p).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+pub trait ToBinary {
+    fn try_to_binary<P: AsRef<Path>>(&self, p: P) -> Result<()>;
+
+    fn to_binary<P: AsRef<Path>>(&self, p: P) {
+        self.try_to_binary(p).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl FromBinary for Molecule {
+    fn try_from_binary<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(&path).map_err(|e| Error::io(&path, e))?;
+        let molecule = decode_molecule(&bytes).map_err(|e| Error::binary(&path, e))?;
+
+        if molecule.lods().is_empty() {
+            return Err(Error::empty_molecule(&path));
+        }
+
+        Ok(molecule)
+    }
+}
+
+impl ToBinary for Molecule {
+    fn try_to_binary<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(&path, encode_molecule(self)).map_err(|e| Error::io(&path, e))
+    }
+}
+
+impl FromBinary for Structure {
+    fn try_from_binary<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(&path).map_err(|e| Error::io(&path, e))?;
+
+        decode_structure(&bytes).map_err(|e| Error::binary(&path, e))
+    }
+}
+
+impl ToBinary for Structure {
+    fn try_to_binary<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let bytes = encode_structure(self).map_err(|e| Error::binary(&path, e))?;
+
+        std::fs::write(&path, bytes).map_err(|e| Error::io(&path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FromRon, ToRon};
+    use nalgebra_glm::{translation, vec4};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rpdb_binary_{}_{}", std::process::id(), name))
+    }
+
+    fn molecule() -> Molecule {
+        let atoms: Vec<Vec4> = (0..10)
+            .map(|i| vec4(i as f32, -(i as f32) * 0.5, 2.0, 1.0 + i as f32 * 0.1))
+            .collect();
+
+        let attributes = (0..atoms.len())
+            .map(|i| AtomAttributes {
+                name: if i % 2 == 0 { "CA" } else { "O" }.to_string(),
+                element: if i % 2 == 0 { "C" } else { "O" }.to_string(),
+                chain: "A".to_string(),
+                residue_name: "GLY".to_string(),
+                residue_id: i as i32 / 2 - 1,
+                b_factor: 20.0 + i as f32,
+            })
+            .collect();
+
+        Molecule {
+            name: "Spike".to_string(),
+            bounding_box: crate::bounding_box(&atoms),
+            lods: vec![
+                MoleculeLod::new(atoms.clone(), 0.0).with_attributes(attributes),
+                MoleculeLod::new(atoms[..4].to_vec(), 50.0)
+                    .with_representatives(vec![0, 0, 1, 1, 1, 2, 2, 3, 3, 3])
+                    .with_error(1.25),
+                MoleculeLod::new(vec![vec4(4.5, -2.25, 2.0, 6.0)], 250.0)
+                    .with_representatives(vec![0; 10])
+                    .with_error(0.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn molecule_round_trip_matches_ron() {
+        let path = temp_path("molecule.ron");
+        molecule().try_to_ron(&path).unwrap();
+        let expected = Molecule::try_from_ron(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(expected.lods()[0].attributes().is_some());
+        assert!(expected.lods()[1].representatives().is_some());
+        assert_eq!(expected.lods()[2].error(), Some(0.0));
+        assert_eq!(
+            decode_molecule(&encode_molecule(&expected)).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn version_1_is_read_without_sections() {
+        let molecule = molecule();
+        let mut bytes = encode_molecule(&molecule);
+        let header = parse_molecule_header(&bytes).unwrap();
+        bytes.truncate(header.atoms_offset + header.atoms_count * ATOM_SIZE);
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+
+        let decoded = decode_molecule(&bytes).unwrap();
+        assert!(decoded.lods()[0].attributes().is_none());
+        for (decoded, lod) in decoded.lods().iter().zip(molecule.lods()) {
+            assert_eq!(decoded.atoms(), lod.atoms());
+            assert_eq!(decoded.breakpoint(), lod.breakpoint());
+        }
+    }
+
+    #[test]
+    fn representatives_outside_of_lod_are_rejected() {
+        let mut molecule = molecule();
+        molecule.lods.truncate(1);
+        molecule.lods[0] = molecule.lods[0].clone().with_representatives(vec![0; 10]);
+
+        let mut bytes = encode_molecule(&molecule);
+        let last = bytes.len() - 4;
+        bytes[last..].copy_from_slice(&10u32.to_le_bytes());
+        assert!(decode_molecule(&bytes).is_err());
+    }
+
+    #[test]
+    fn structure_round_trip_matches_ron() {
+        let mut molecules = HashMap::new();
+        molecules.insert(
+            "Spike".to_string(),
+            vec![Mat4::identity(), translation(&vec3(1.0, 2.0, 3.0))],
+        );
+        molecules.insert("M".to_string(), vec![translation(&vec3(-4.0, 0.5, 8.0))]);
+
+        let path = temp_path("structure.ron");
+        Structure::new(molecules).try_to_ron(&path).unwrap();
+        let expected = Structure::try_from_ron(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            decode_structure(&encode_structure(&expected).unwrap()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn compartments_are_rejected() {
+        let mut envelope = crate::structure::Compartment::new("envelope");
+        envelope.transform = translation(&vec3(10.0, 0.0, 0.0));
+        envelope
+            .molecules
+            .insert("M".to_string(), vec![Mat4::identity()]);
+
+        let mut structure = Structure::new(HashMap::new());
+        structure.compartments.push(envelope);
+
+        assert!(encode_structure(&structure).is_err());
+
+        // Flattened explicitly, their instances are stored
+        let flattened = Structure::new(structure.flatten());
+        let decoded = decode_structure(&encode_structure(&flattened).unwrap()).unwrap();
+        assert_eq!(decoded, flattened);
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = encode_molecule(&molecule());
+        bytes[0] = b'X';
+        assert!(decode_molecule(&bytes).is_err());
+
+        // Files of one kind are not accepted as the other
+        assert!(decode_structure(&encode_molecule(&molecule())).is_err());
+        assert!(
+            decode_molecule(&encode_structure(&Structure::new(HashMap::new())).unwrap()).is_err()
+        );
+    }
+
+    #[test]
+    fn wrong_version_is_rejected() {
+        let mut bytes = encode_molecule(&molecule());
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(decode_molecule(&bytes).is_err());
+
+        let mut bytes = encode_structure(&Structure::new(HashMap::new())).unwrap();
+        bytes[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(decode_structure(&bytes).is_err());
+    }
+
+    #[test]
+    fn corrupted_counts_are_rejected() {
+        // Lods count, atoms count
+        for offset in [8, 12].iter() {
+            let mut bytes = encode_molecule(&molecule());
+            bytes[*offset..*offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(decode_molecule(&bytes).is_err(), "{}", offset);
+            assert!(parse_molecule_header(&bytes).is_err(), "{}", offset);
+        }
+
+        // Molecules count, instances count
+        let mut molecules = HashMap::new();
+        molecules.insert("Spike".to_string(), vec![Mat4::identity(); 3]);
+        for offset in [8, 12].iter() {
+            let mut bytes = encode_structure(&Structure::new(molecules.clone())).unwrap();
+            bytes[*offset..*offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(decode_structure(&bytes).is_err(), "{}", offset);
+        }
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let bytes = encode_molecule(&molecule());
+        for len in 0..bytes.len() {
+            assert!(decode_molecule(&bytes[..len]).is_err(), "{}", len);
+        }
+
+        let mut molecules = HashMap::new();
+        molecules.insert("Spike".to_string(), vec![Mat4::identity(); 3]);
+        let bytes = encode_structure(&Structure::new(molecules)).unwrap();
+        for len in 0..bytes.len() {
+            assert!(decode_structure(&bytes[..len]).is_err(), "{}", len);
+        }
+    }
+}
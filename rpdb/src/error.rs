@@ -0,0 +1,138 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Errors that can occur while loading or storing molecules and structures.
+#[derive(Debug)]
+pub enum Error {
+    /// File could not be read or written.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    /// File is not a valid RON document or does not match the expected layout.
+    Ron {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    /// Text file such as mmCIF could not be parsed. Line is 0 when unknown.
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+
+    /// File is not a valid binary molecule or structure.
+    Binary { path: PathBuf, message: String },
+
+    /// Data could not be serialized into RON.
+    Serialize { path: PathBuf, message: String },
+
+    /// Molecule does not contain any atoms.
+    EmptyMolecule { path: PathBuf },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn io<P: AsRef<Path>>(path: P, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn ron<P: AsRef<Path>>(path: P, error: ron::Error) -> Self {
+        Error::Ron {
+            path: path.as_ref().to_path_buf(),
+            line: error.position.line,
+            column: error.position.col,
+            message: error.code.to_string(),
+        }
+    }
+
+    pub(crate) fn parse<P: AsRef<Path>>(path: P, line: usize, message: String) -> Self {
+        Error::Parse {
+            path: path.as_ref().to_path_buf(),
+            line,
+            message,
+        }
+    }
+
+    pub(crate) fn binary<P: AsRef<Path>>(path: P, message: String) -> Self {
+        Error::Binary {
+            path: path.as_ref().to_path_buf(),
+            message,
+        }
+    }
+
+    pub(crate) fn serialize<P: AsRef<Path>>(path: P, error: ron::Error) -> Self {
+        Error::Serialize {
+            path: path.as_ref().to_path_buf(),
+            message: error.to_string(),
+        }
+    }
+
+    pub(crate) fn empty_molecule<P: AsRef<Path>>(path: P) -> Self {
+        Error::EmptyMolecule {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Path of the file the error originated from.
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::Io { path, .. }
+            | Error::Ron { path, .. }
+            | Error::Parse { path, .. }
+            | Error::Binary { path, .. }
+            | Error::Serialize { path, .. }
+            | Error::EmptyMolecule { path } => path,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Ron {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            Error::Parse {
+                path,
+                line: 0,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+            Error::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            Error::Binary { path, message } => {
+                write!(f, "{}: invalid binary file: {}", path.display(), message)
+            }
+            Error::Serialize { path, message } => {
+                write!(f, "{}: could not serialize: {}", path.display(), message)
+            }
+            Error::EmptyMolecule { path } => {
+                write!(f, "{}: molecule contains no atoms", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
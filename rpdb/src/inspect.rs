@@ -0,0 +1,354 @@
+//! Validation and statistics of molecule and structure files.
+use crate::error::Error;
+use crate::molecule::Molecule;
+use crate::structure::{molecule_path, Structure};
+use crate::{is_molecule_file, BoundingBox, FromFile};
+
+use nalgebra_glm::{max2, min2, vec3, vec4, Mat4};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// File could not be read or parsed.
+    InvalidFile,
+    /// Molecule referenced by a structure does not exist next to it.
+    UnresolvedReference,
+    /// Structure references a molecule without any instances.
+    NoInstances,
+    /// Transform of an instance contains NaN or infinity.
+    NonFiniteTransform,
+    /// Atom contains NaN or infinity, or has a non-positive radius.
+    InvalidAtom,
+    /// LOD has no atoms.
+    EmptyLod,
+    /// LOD breakpoints do not strictly increase.
+    NonMonotonicBreakpoints,
+    /// Mapping of LOD 0 atoms to spheres of a LOD does not match the atom counts.
+    InvalidRepresentatives,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub molecule: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LodReport {
+    pub breakpoint: f32,
+    pub max_radius: f32,
+    pub atoms: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<f32>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MoleculeReport {
+    pub name: String,
+    pub path: String,
+    /// Number of instances in the structure, 1 for standalone molecules.
+    pub instances: usize,
+    pub lods: Vec<LodReport>,
+    /// Atoms of LOD 0 of all instances.
+    pub atoms_total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounding_box: Option<BoundingBox>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Report {
+    pub path: String,
+    /// `structure` or `molecule`.
+    pub kind: &'static str,
+    pub molecules: Vec<MoleculeReport>,
+    pub instances_total: usize,
+    pub atoms_total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounding_box: Option<BoundingBox>,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+fn issue(kind: IssueKind, molecule: Option<&str>, message: String) -> Issue {
+    Issue {
+        kind,
+        molecule: molecule.map(|m| m.to_string()),
+        message,
+    }
+}
+
+/// Checks LODs of a molecule and returns their statistics.
+fn check_molecule(molecule: &Molecule, issues: &mut Vec<Issue>) -> Vec<LodReport> {
+    let name = Some(molecule.name());
+    let mut lods = Vec::new();
+
+    for (index, lod) in molecule.lods().iter().enumerate() {
+        if lod.atoms().is_empty() {
+            issues.push(issue(
+                IssueKind::EmptyLod,
+                name,
+                format!("LOD {} has no atoms", index),
+            ));
+        }
+
+        let invalid_atoms = lod
+            .atoms()
+            .iter()
+            .filter(|atom| !atom.iter().all(|v| v.is_finite()) || atom.w <= 0.0)
+            .count();
+        if invalid_atoms > 0 {
+            issues.push(issue(
+                IssueKind::InvalidAtom,
+                name,
+                format!(
+                    "LOD {} has {} atoms with non-finite values or non-positive radius",
+                    index, invalid_atoms
+                ),
+            ));
+        }
+
+        if index > 0 && lod.breakpoint() <= molecule.lods()[index - 1].breakpoint() {
+            issues.push(issue(
+                IssueKind::NonMonotonicBreakpoints,
+                name,
+                format!(
+                    "breakpoint of LOD {} ({}) is not greater than of LOD {} ({})",
+                    index,
+                    lod.breakpoint(),
+                    index - 1,
+                    molecule.lods()[index - 1].breakpoint()
+                ),
+            ));
+        }
+
+        if let Some(representatives) = lod.representatives() {
+            if representatives.len() != molecule.lods()[0].atoms().len()
+                || representatives
+                    .iter()
+                    .any(|r| *r as usize >= lod.atoms().len())
+            {
+                issues.push(issue(
+                    IssueKind::InvalidRepresentatives,
+                    name,
+                    format!("LOD {} maps LOD 0 atoms to non-existent spheres", index),
+                ));
+            }
+        }
+
+        lods.push(LodReport {
+            breakpoint: lod.breakpoint(),
+            max_radius: lod.max_radius(),
+            atoms: lod.atoms().len(),
+            error: lod.error(),
+        });
+    }
+
+    lods
+}
+
+/// Bounding box of a box transformed by `transform`.
+fn transform_bounding_box(bounding_box: &BoundingBox, transform: &Mat4) -> BoundingBox {
+    let mut min = vec3(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+    let mut max = -min;
+
+    for corner in 0..8 {
+        let x = if corner & 1 == 0 {
+            bounding_box.min.x
+        } else {
+            bounding_box.max.x
+        };
+        let y = if corner & 2 == 0 {
+            bounding_box.min.y
+        } else {
+            bounding_box.max.y
+        };
+        let z = if corner & 4 == 0 {
+            bounding_box.min.z
+        } else {
+            bounding_box.max.z
+        };
+
+        let corner = (transform * vec4(x, y, z, 1.0)).xyz();
+        min = min2(&min, &corner);
+        max = max2(&max, &corner);
+    }
+
+    BoundingBox { min, max }
+}
+
+fn empty_report<P: AsRef<Path>>(path: P, kind: &'static str) -> Report {
+    Report {
+        path: path.as_ref().display().to_string(),
+        kind,
+        molecules: Vec::new(),
+        instances_total: 0,
+        atoms_total: 0,
+        bounding_box: None,
+        issues: Vec::new(),
+    }
+}
+
+/// Report of a file that could not be read or parsed as `kind`.
+fn invalid_report<P: AsRef<Path>>(path: P, kind: &'static str, error: Error) -> Report {
+    let mut report = empty_report(path, kind);
+    report
+        .issues
+        .push(issue(IssueKind::InvalidFile, None, error.to_string()));
+
+    report
+}
+
+/// Validates a standalone molecule read from `path`.
+pub fn inspect_molecule<P: AsRef<Path>>(path: P, molecule: &Molecule) -> Report {
+    let mut report = empty_report(path, "molecule");
+
+    let lods = check_molecule(molecule, &mut report.issues);
+    let atoms_total = lods.first().map_or(0, |lod| lod.atoms);
+
+    report.instances_total = 1;
+    report.atoms_total = atoms_total;
+    report.bounding_box = Some(*molecule.bounding_box());
+    report.molecules.push(MoleculeReport {
+        name: molecule.name().to_string(),
+        path: report.path.clone(),
+        instances: 1,
+        lods,
+        atoms_total,
+        bounding_box: Some(*molecule.bounding_box()),
+    });
+
+    report
+}
+
+/// Validates a structure read from `path` and all molecules it references, which are resolved relative to `path`.
+pub fn inspect_structure<P: AsRef<Path>>(path: P, structure: &Structure) -> Report {
+    let mut report = empty_report(&path, "structure");
+
+    let mut names: Vec<(String, Vec<Mat4>)> = structure.flatten().into_iter().collect();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (name, transforms) in names {
+        let molecule_file = molecule_path(&path, &name);
+        report.instances_total += transforms.len();
+
+        if transforms.is_empty() {
+            report.issues.push(issue(
+                IssueKind::NoInstances,
+                Some(&name),
+                "molecule has no instances".to_string(),
+            ));
+        }
+
+        let non_finite = transforms
+            .iter()
+            .filter(|t| !t.iter().all(|v| v.is_finite()))
+            .count();
+        if non_finite > 0 {
+            report.issues.push(issue(
+                IssueKind::NonFiniteTransform,
+                Some(&name),
+                format!(
+                    "{} of {} transforms are not finite",
+                    non_finite,
+                    transforms.len()
+                ),
+            ));
+        }
+
+        if !molecule_file.exists() {
+            report.issues.push(issue(
+                IssueKind::UnresolvedReference,
+                Some(&name),
+                format!("{} does not exist", molecule_file.display()),
+            ));
+            continue;
+        }
+
+        let molecule = match Molecule::try_from_file(&molecule_file) {
+            Ok(molecule) => molecule,
+            Err(e) => {
+                report
+                    .issues
+                    .push(issue(IssueKind::InvalidFile, Some(&name), e.to_string()));
+                continue;
+            }
+        };
+
+        let lods = check_molecule(&molecule, &mut report.issues);
+        let atoms_total = lods.first().map_or(0, |lod| lod.atoms) * transforms.len();
+        report.atoms_total += atoms_total;
+
+        for transform in transforms
+            .iter()
+            .filter(|t| t.iter().all(|v| v.is_finite()))
+        {
+            let instance_box = transform_bounding_box(molecule.bounding_box(), transform);
+            report.bounding_box = Some(match report.bounding_box {
+                Some(bounding_box) => bounding_box.union(&instance_box),
+                None => instance_box,
+            });
+        }
+
+        report.molecules.push(MoleculeReport {
+            name,
+            path: molecule_file.display().to_string(),
+            instances: transforms.len(),
+            lods,
+            atoms_total,
+            bounding_box: Some(*molecule.bounding_box()),
+        });
+    }
+
+    report
+}
+
+/// Validates a molecule or a structure file, telling them apart by `is_molecule_file` so that a malformed file is
+/// reported with the errors of its own parser.
+pub fn inspect<P: AsRef<Path>>(path: P) -> Report {
+    if is_molecule_file(&path) {
+        match Molecule::try_from_file(&path) {
+            Ok(molecule) => inspect_molecule(&path, &molecule),
+            Err(e) => invalid_report(&path, "molecule", e),
+        }
+    } else {
+        match Structure::try_from_file(&path) {
+            Ok(structure) => inspect_structure(&path, &structure),
+            Err(e) => invalid_report(&path, "structure", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_files_are_reported_by_their_own_parser() {
+        let files = [
+            ("molecule.ron", "(name: \"M\", lods: ", "molecule"),
+            ("structure.ron", "(molecules: {\"M\": [}", "structure"),
+        ];
+
+        for (name, content, kind) in files.iter() {
+            let path =
+                std::env::temp_dir().join(format!("rpdb_inspect_{}_{}", std::process::id(), name));
+            std::fs::write(&path, content).unwrap();
+
+            let report = inspect(&path);
+            assert_eq!(report.kind, *kind);
+            assert_eq!(report.issues.len(), 1);
+            assert_eq!(report.issues[0].kind, IssueKind::InvalidFile);
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -1,14 +1,24 @@
+pub mod args;
+pub mod assembly;
+pub mod atom;
+pub mod binary;
+pub mod error;
+pub mod inspect;
 pub mod lod;
+pub mod mapped;
+pub mod mmcif;
 pub mod molecule;
+pub mod pdb;
+pub mod radii;
+pub mod recipe;
 pub mod structure;
 
-use lib3dmol::{
-    parser::read_pdb,
-    structures::{atom::AtomType, GetAtom},
-};
-use nalgebra_glm::{max2, min2, vec3, vec4, Vec3, Vec4};
+use nalgebra_glm::{max2, min2, vec3, Vec3, Vec4};
 use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+
+pub use error::{Error, Result};
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
 pub struct BoundingBox {
     pub min: Vec3,
     pub max: Vec3,
@@ -81,66 +91,191 @@ pub fn center_atoms(mut atoms: Vec<Vec4>) -> Vec<Vec4> {
     atoms
 }
 
-pub trait FromRon {
-    fn from_ron<P: AsRef<std::path::Path>>(p: P) -> Self;
+pub trait FromRon: Sized {
+    fn try_from_ron<P: AsRef<std::path::Path>>(p: P) -> Result<Self>;
+
+    fn from_ron<P: AsRef<std::path::Path>>(p: P) -> Self {
+        Self::try_from_ron(p).unwrap_or_else(|e| panic!("{}", e))
+    }
 }
 
 pub trait ToRon {
-    fn to_ron<P: AsRef<std::path::Path>>(&self, p: P);
+    fn try_to_ron<P: AsRef<std::path::Path>>(&self, p: P) -> Result<()>;
+
+    fn to_ron<P: AsRef<std::path::Path>>(&self, p: P) {
+        self.try_to_ron(p).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+fn read_ron<T, P>(path: P) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+    P: AsRef<std::path::Path>,
+{
+    let file = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+    ron::de::from_str(&file).map_err(|e| Error::ron(&path, e))
 }
 
 impl FromRon for molecule::Molecule {
-    fn from_ron<P: AsRef<std::path::Path>>(path: P) -> Self {
-        let file = std::fs::read_to_string(&path).expect("Could not open structure file.");
+    fn try_from_ron<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let molecule: molecule::Molecule = read_ron(&path)?;
+
+        if molecule.lods().is_empty() || molecule.lods()[0].atoms().is_empty() {
+            return Err(Error::empty_molecule(&path));
+        }
 
-        ron::de::from_str(&file).expect("Could not deserialize structure file.")
+        Ok(molecule)
     }
 }
 
 impl ToRon for molecule::Molecule {
-    fn to_ron<P: AsRef<std::path::Path>>(&self, path: P) {
+    fn try_to_ron<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
         let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
-            .expect("Could not serialize the molecule.");
-        std::fs::write(&path, data).expect("Could not write the molecule.");
+            .map_err(|e| Error::serialize(&path, e))?;
+
+        std::fs::write(&path, data).map_err(|e| Error::io(&path, e))
+    }
+}
+
+/// Loads a file in either the binary or the RON format, detected by the magic bytes at its start.
+pub trait FromFile: Sized {
+    fn try_from_file<P: AsRef<std::path::Path>>(p: P) -> Result<Self>;
+
+    fn from_file<P: AsRef<std::path::Path>>(p: P) -> Self {
+        Self::try_from_file(p).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<T: FromRon + binary::FromBinary> FromFile for T {
+    fn try_from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        if binary::is_binary_file(&path)? {
+            T::try_from_binary(path)
+        } else {
+            T::try_from_ron(path)
+        }
+    }
+}
+
+/// Whether the file is a molecule rather than a structure, told from its magic bytes or from the first field of its
+/// RON struct without parsing the whole file.
+pub fn is_molecule_file<P: AsRef<std::path::Path>>(path: P) -> bool {
+    use std::io::Read;
+
+    let mut start = Vec::with_capacity(256);
+    let read = std::fs::File::open(path).and_then(|file| file.take(256).read_to_end(&mut start));
+    if read.is_err() {
+        return false;
     }
+
+    if binary::is_binary(&start) {
+        return start.starts_with(&binary::MOLECULE_MAGIC);
+    }
+
+    let start = String::from_utf8_lossy(&start);
+    let first_field = start.trim_start().strip_prefix('(').map(|fields| {
+        fields
+            .trim_start()
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .next()
+            .unwrap_or("")
+    });
+
+    matches!(
+        first_field,
+        Some("name") | Some("bounding_box") | Some("lods")
+    )
 }
 
 pub trait FromPdb {
-    fn from_pdb<P: AsRef<std::path::Path>>(p: P) -> molecule::Molecule;
+    fn try_from_pdb_with_radii<P: AsRef<std::path::Path>>(
+        p: P,
+        radii: &radii::RadiusTable,
+    ) -> Result<molecule::Molecule>;
+
+    fn try_from_pdb<P: AsRef<std::path::Path>>(p: P) -> Result<molecule::Molecule> {
+        Self::try_from_pdb_with_radii(p, &radii::RadiusTable::default())
+    }
+
+    fn from_pdb<P: AsRef<std::path::Path>>(p: P) -> molecule::Molecule {
+        Self::try_from_pdb(p).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+/// Name of a molecule read from a coordinate file, given by the file name.
+fn file_molecule_name<P: AsRef<std::path::Path>>(path: P) -> String {
+    path.as_ref()
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_ascii_uppercase())
+        .unwrap_or_default()
+}
+
+fn molecule_from_records<P: AsRef<std::path::Path>>(
+    path: P,
+    name: &str,
+    records: &[atom::AtomRecord],
+    radii: &radii::RadiusTable,
+) -> Result<molecule::Molecule> {
+    if !records.iter().any(|record| radii.radius(record).is_some()) {
+        return Err(Error::empty_molecule(&path));
+    }
+
+    Ok(atom::molecule_from_records(name, records, radii))
 }
 
 impl FromPdb for molecule::Molecule {
-    fn from_pdb<P: AsRef<std::path::Path>>(path: P) -> molecule::Molecule {
-        let molecule_structure = read_pdb(path.as_ref().to_str().unwrap(), "");
-
-        let mut atoms = Vec::new();
-        for atom in molecule_structure.get_atom() {
-            let radius = match atom.a_type {
-                AtomType::Carbon => 1.548,
-                AtomType::Hydrogen => 1.100,
-                AtomType::Nitrogen => 1.400,
-                AtomType::Oxygen => 1.348,
-                AtomType::Phosphorus => 1.880,
-                AtomType::Sulfur => 1.880,
-                _ => 1.0, // 'A': 1.5
-            };
-            atoms.push(vec4(atom.coord[0], atom.coord[1], atom.coord[2], radius));
-        }
+    fn try_from_pdb_with_radii<P: AsRef<std::path::Path>>(
+        path: P,
+        radii: &radii::RadiusTable,
+    ) -> Result<molecule::Molecule> {
+        let records = pdb::read_atom_records(&path)?;
 
-        atoms = center_atoms(atoms);
+        molecule_from_records(&path, &file_molecule_name(&path), &records, radii)
+    }
+}
 
-        molecule::Molecule {
-            name: molecule_structure.name().to_string(),
-            bounding_box: bounding_box(&atoms),
-            lods: vec![molecule::MoleculeLod::new(atoms, 0.0)],
-        }
+pub trait FromCif {
+    fn try_from_cif_with_radii<P: AsRef<std::path::Path>>(
+        p: P,
+        radii: &radii::RadiusTable,
+    ) -> Result<molecule::Molecule>;
+
+    fn try_from_cif<P: AsRef<std::path::Path>>(p: P) -> Result<molecule::Molecule> {
+        Self::try_from_cif_with_radii(p, &radii::RadiusTable::default())
+    }
+
+    fn from_cif<P: AsRef<std::path::Path>>(p: P) -> molecule::Molecule {
+        Self::try_from_cif(p).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl FromCif for molecule::Molecule {
+    fn try_from_cif_with_radii<P: AsRef<std::path::Path>>(
+        path: P,
+        radii: &radii::RadiusTable,
+    ) -> Result<molecule::Molecule> {
+        let (name, records) = mmcif::read_atom_records(&path)?;
+
+        molecule_from_records(&path, &name, &records, radii)
+    }
+}
+
+impl FromRon for recipe::RecipeConfig {
+    fn try_from_ron<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        read_ron(path)
     }
 }
 
 impl FromRon for structure::Structure {
-    fn from_ron<P: AsRef<std::path::Path>>(path: P) -> Self {
-        let file = std::fs::read_to_string(&path).expect("Could not open structure file.");
+    fn try_from_ron<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        read_ron(path)
+    }
+}
+
+impl ToRon for structure::Structure {
+    fn try_to_ron<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        let data = ron::ser::to_string(self).map_err(|e| Error::serialize(&path, e))?;
 
-        ron::de::from_str(&file).expect("Could not deserialize structure file.")
+        std::fs::write(&path, data).map_err(|e| Error::io(&path, e))
     }
 }
@@ -1,5 +1,7 @@
 use crate::molecule::*;
+use kmeans::Reducer;
 use nalgebra_glm::*;
+use std::collections::HashMap;
 
 fn sphere_sreen_space_area(projection: Mat4, dimensions: Vec2, center: Vec3, radius: f32) -> f32 {
     let d2 = dot(&center, &center);
@@ -42,104 +44,319 @@ fn sphere_sreen_space_area(projection: Mat4, dimensions: Vec2, center: Vec3, rad
     area
 }
 
-pub trait Loddable {
-    fn create_lods(&mut self);
+/// Identifier of the residue of each atom, for reducers coarse-graining by residues.
+fn residue_ids(lod: &MoleculeLod) -> Option<Vec<u32>> {
+    let mut ids: HashMap<(&str, i32, &str), u32> = HashMap::new();
+
+    lod.attributes().map(|attributes| {
+        attributes
+            .iter()
+            .map(|a| {
+                let next_id = ids.len() as u32;
+                *ids.entry((a.chain.as_str(), a.residue_id, a.residue_name.as_str()))
+                    .or_insert(next_id)
+            })
+            .collect()
+    })
 }
 
-impl Loddable for Molecule {
-    fn create_lods(&mut self) {
-        // Create new LODs
-        let mut lods = Vec::new();
-        lods.push(self.lods()[0].clone());
+/// Numbers of spheres of the LOD candidates.
+#[derive(Clone, Debug)]
+pub enum LodTargets {
+    /// Fractions of the number of atoms of the first LOD.
+    Ratios(Vec<f32>),
+    /// Absolute numbers of spheres.
+    Counts(Vec<usize>),
+}
+
+impl LodTargets {
+    /// Number of spheres of each candidate for a molecule of `atoms_count` atoms, at least 1.
+    pub fn counts(&self, atoms_count: usize) -> Vec<usize> {
+        match self {
+            LodTargets::Ratios(ratios) => ratios
+                .iter()
+                .map(|ratio| ((atoms_count as f32 * ratio) as usize).max(1))
+                .collect(),
+            LodTargets::Counts(counts) => counts.iter().map(|count| (*count).max(1)).collect(),
+        }
+    }
+}
 
-        // Constants
-        let width = 1920.0;
-        let height = 1080.0;
-        let aspect = width / height;
-        let projection = infinite_perspective_rh_no(aspect, 0.785398163, 0.1);
-        let ratios = [
-            0.9, 0.75, 0.5, 0.25, 0.1,
-            0.075, 0.05, 0.025, 0.01,
-            0.005, 0.001,
-        ];
-        let area_threshold = 32.0;
+/// Search of the distance at which a LOD becomes too small on the screen.
+#[derive(Copy, Clone, Debug)]
+pub enum BreakpointSearch {
+    /// Zooms out by `step` until the area falls below the threshold.
+    Linear { step: f32 },
+    /// Brackets the distance by doubling it and then bisects the bracket down to `tolerance`.
+    Bisection { tolerance: f32 },
+}
 
-        // Current largest radius that is being projected
-        let mut radius = lods.last().unwrap().max_radius();
-        let mut z = radius * 2.0;
+/// Parameters of LOD creation.
+#[derive(Clone, Debug)]
+pub struct LodConfig {
+    /// Resolution of the viewport in pixels.
+    pub width: f32,
+    pub height: f32,
+
+    /// Vertical field of view in radians.
+    pub fov: f32,
+
+    /// A LOD is replaced by a coarser one when its largest sphere covers fewer pixels than this.
+    pub area_threshold: f32,
+
+    /// Candidates of the coarser LODs, ordered from the finest.
+    pub targets: LodTargets,
 
-        // Iteratively zoom out
-        let mut current_ratio_index = 0;
-        'main: loop {
-            z += 1.0;
+    pub search: BreakpointSearch,
 
-            if current_ratio_index >= ratios.len() - 1 {
-                break 'main;
+    /// With `create_lods_by_error`, a LOD is used once its geometric error projects to fewer pixels than this.
+    pub error_threshold: f32,
+}
+
+impl Default for LodConfig {
+    fn default() -> Self {
+        Self {
+            width: 1920.0,
+            height: 1080.0,
+            fov: std::f32::consts::FRAC_PI_4,
+            area_threshold: 32.0,
+            targets: LodTargets::Ratios(vec![
+                0.9, 0.75, 0.5, 0.25, 0.1, 0.075, 0.05, 0.025, 0.01, 0.005, 0.001,
+            ]),
+            search: BreakpointSearch::Bisection { tolerance: 0.01 },
+            error_threshold: 1.0,
+        }
+    }
+}
+
+impl LodConfig {
+    fn projection(&self) -> Mat4 {
+        infinite_perspective_rh_no(self.width / self.height, self.fov, 0.1)
+    }
+
+    /// Area in pixels of a sphere of `radius` at the distance `z` in front of the camera.
+    pub fn area(&self, z: f32, radius: f32) -> f32 {
+        sphere_sreen_space_area(
+            self.projection(),
+            vec2(self.width, self.height),
+            vec3(0.0, 0.0, -z),
+            radius,
+        )
+    }
+
+    fn is_below_threshold(&self, z: f32, radius: f32) -> bool {
+        let area = self.area(z, radius);
+        area.is_finite() && area < self.area_threshold
+    }
+
+    /// Distance beyond which a length of `error` projects to fewer pixels than the error threshold.
+    pub fn error_breakpoint(&self, error: f32) -> f32 {
+        let pixels_per_unit_at_unit_distance = self.height * 0.5 / (self.fov * 0.5).tan();
+
+        error * pixels_per_unit_at_unit_distance / self.error_threshold
+    }
+
+    /// Smallest distance beyond `start` at which a sphere of `radius` is below the area threshold.
+    pub fn breakpoint(&self, start: f32, radius: f32) -> f32 {
+        match self.search {
+            BreakpointSearch::Linear { step } => {
+                let mut z = start + step;
+                while !self.is_below_threshold(z, radius) && z.is_finite() {
+                    z += step;
+                }
+                z
             }
+            BreakpointSearch::Bisection { tolerance } => {
+                let mut near = start;
+                let mut far = start.max(radius).max(1.0);
+                while !self.is_below_threshold(far, radius) && far.is_finite() {
+                    near = far;
+                    far *= 2.0;
+                }
 
-            let view = look_at(
-                &vec3(0.0, 0.0, z as f32),
-                &vec3(0.0, 0.0, 0.0),
-                &vec3(0.0, 1.0, 0.0),
-            );
-            let position = view * vec4(0.0, 0.0, 0.0, 1.0);
-            let area =
-                sphere_sreen_space_area(projection, vec2(width, height), position.xyz(), radius);
-
-            if !area.is_finite() {
-                continue;
+                while far - near > tolerance {
+                    let mid = (near + far) * 0.5;
+                    if self.is_below_threshold(mid, radius) {
+                        far = mid;
+                    } else {
+                        near = mid;
+                    }
+                }
+                far
             }
+        }
+    }
+}
 
-            // Breakpoint at area limit
-            // Currently 64 = 8x8 pixel area
-            if area < area_threshold {
-                // println!("{:?}", area);
-                // println!("Distance: {}", z);
+/// Number of samples on the surface of each reduced sphere when measuring the error of a LOD.
+const ERROR_SAMPLES: usize = 64;
 
-                // Continue along the reduction ratios
-                for reduction_ratio in current_ratio_index..ratios.len() {
-                    let mut new_centroids_num =
-                        (lods[0].atoms().len() as f32 * ratios[reduction_ratio]) as usize;
+/// Directions of `count` points distributed nearly uniformly on a unit sphere along a Fibonacci spiral, from the
+/// top (+Y) to the bottom. None of them lies exactly on a pole.
+pub fn fibonacci_directions(count: usize) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
 
-                    // End if It is not possible to reduce further
-                    if lods.last().unwrap().atoms().len() == 1 {
-                        break 'main;
-                    }
+    (0..count)
+        .map(|i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = (1.0 - y * y).sqrt();
+            let phi = golden_angle * i as f32;
 
-                    if new_centroids_num < 1 {
-                        new_centroids_num = 1;
-                    }
+            vec3(r * phi.cos(), y, r * phi.sin())
+        })
+        .collect()
+}
 
-                    let new_means = kmeans::reduce(lods[0].atoms(), new_centroids_num);
+/// Geometric error of `lod` with respect to the atoms it was reduced from, `None` without representatives.
+///
+/// For each sphere, the Hausdorff distance between the sphere and the union of the atoms it represents is measured,
+/// exactly from the atoms to the sphere and on samples of its surface from the sphere to the atoms.
+/// The maximum over all spheres estimates the Hausdorff distance between the unions of atoms and spheres, sampling
+/// the sphere surface in `ERROR_SAMPLES` directions.
+pub fn lod_error(atoms: &[Vec4], lod: &MoleculeLod) -> Option<f32> {
+    let represented = lod.represented_atoms()?;
+    let directions = fibonacci_directions(ERROR_SAMPLES);
+
+    let error = lod
+        .atoms()
+        .iter()
+        .zip(represented.iter())
+        .map(|(sphere, members)| {
+            // Furthest reach of the atoms out of the sphere
+            let atoms_to_sphere = members
+                .iter()
+                .map(|a| {
+                    let atom = atoms[*a as usize];
+                    distance(&atom.xyz(), &sphere.xyz()) + atom.w - sphere.w
+                })
+                .fold(0.0f32, f32::max);
+
+            // Furthest surface point of the sphere from all atoms
+            let sphere_to_atoms = directions
+                .iter()
+                .map(|direction| {
+                    let sample = sphere.xyz() + direction * sphere.w;
+                    members
+                        .iter()
+                        .map(|a| {
+                            let atom = atoms[*a as usize];
+                            distance(&sample, &atom.xyz()) - atom.w
+                        })
+                        .fold(std::f32::INFINITY, f32::min)
+                        .max(0.0)
+                })
+                .fold(0.0f32, f32::max);
+
+            atoms_to_sphere.max(sphere_to_atoms)
+        })
+        .fold(0.0f32, f32::max);
+
+    Some(error)
+}
 
-                    if new_means.len() == 0 {
-                        continue;
-                    }
+fn reduce_lod(
+    reducer: &dyn Reducer,
+    atoms: &[Vec4],
+    residues: Option<&[u32]>,
+    count: usize,
+) -> MoleculeLod {
+    let (spheres, memberships) = reducer.reduce(atoms, residues, count);
 
-                    let mut new_lod = MoleculeLod::new(new_means, 0.0);
+    MoleculeLod::new(spheres, 0.0)
+        .with_representatives(memberships.into_iter().map(|m| m as u32).collect())
+}
 
-                    let new_area = sphere_sreen_space_area(
-                        projection,
-                        vec2(width, height),
-                        position.xyz(),
-                        new_lod.max_radius(),
-                    );
+pub trait Loddable {
+    /// Replaces all LODs but the first one by its simplifications created by `reducer`. Each LOD is used
+    /// once the largest sphere of the previous one covers fewer pixels than the area threshold.
+    /// The result is reproducible as long as the reducer is, e.g. k-means with a fixed seed.
+    fn create_lods(&mut self, reducer: &dyn Reducer, config: &LodConfig);
+
+    /// Replaces all LODs but the first one by its simplifications created by `reducer`. Each LOD is used
+    /// once its geometric error, stored in the LOD, projects to fewer pixels than the error threshold.
+    /// Candidates which would never be used, because a coarser one is acceptable as early, are left out.
+    fn create_lods_by_error(&mut self, reducer: &dyn Reducer, config: &LodConfig);
+}
 
-                    // println!("Current ratio: {}. New centroids: {}. New means real len: {}. New area: {}", ratios[reduction_ratio], new_centroids_num, new_lod.atoms().len(), new_area);
+impl Loddable for Molecule {
+    fn create_lods(&mut self, reducer: &dyn Reducer, config: &LodConfig) {
+        // Create new LODs
+        let mut lods = Vec::new();
+        lods.push(self.lods()[0].clone());
+        let residues = residue_ids(&lods[0]);
 
-                    // If the new are is now above the area limit, save It and continue
-                    if new_area > area_threshold {
-                        radius = new_lod.max_radius();
+        // Candidates are reduced at most once, when first needed
+        let counts = config.targets.counts(lods[0].atoms().len());
+        let mut candidates: Vec<Option<MoleculeLod>> = vec![None; counts.len()];
 
-                        new_lod.set_breakpoint(z);
-                        lods.push(new_lod);
+        // Current largest radius that is being projected
+        let mut radius = lods[0].max_radius();
+        let mut z = radius * 2.0;
 
-                        current_ratio_index = reduction_ratio + 1;
-                        break;
-                    }
+        let mut current_index = 0;
+        while current_index < counts.len() && lods.last().unwrap().atoms().len() > 1 {
+            // Distance at which the current LOD becomes too small
+            z = config.breakpoint(z, radius);
+
+            // The first candidate with large enough spheres at that distance replaces it
+            let mut next = None;
+            for index in current_index..counts.len() {
+                let candidate = candidates[index].get_or_insert_with(|| {
+                    reduce_lod(reducer, lods[0].atoms(), residues.as_deref(), counts[index])
+                });
+
+                if config.area(z, candidate.max_radius()) > config.area_threshold {
+                    next = Some(index);
+                    break;
                 }
             }
+
+            // Coarser LODs would be too small as well
+            let next = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            let mut new_lod = candidates[next].take().unwrap();
+            new_lod.set_breakpoint(z);
+            radius = new_lod.max_radius();
+            lods.push(new_lod);
+
+            current_index = next + 1;
+        }
+
+        self.lods = lods;
+    }
+
+    fn create_lods_by_error(&mut self, reducer: &dyn Reducer, config: &LodConfig) {
+        let lod0 = self.lods()[0].clone().with_error(0.0);
+        let residues = residue_ids(&lod0);
+
+        let mut candidates: Vec<MoleculeLod> = config
+            .targets
+            .counts(lod0.atoms().len())
+            .into_iter()
+            .filter(|count| *count < lod0.atoms().len())
+            .map(|count| {
+                let lod = reduce_lod(reducer, lod0.atoms(), residues.as_deref(), count);
+                let error = lod_error(lod0.atoms(), &lod).unwrap();
+                let mut lod = lod.with_error(error);
+                lod.set_breakpoint(config.error_breakpoint(error));
+                lod
+            })
+            .collect();
+
+        // Keep candidates whose breakpoints increase from the finest to the coarsest
+        let mut lods = Vec::new();
+        let mut coarser_breakpoint = std::f32::INFINITY;
+        while let Some(candidate) = candidates.pop() {
+            if candidate.breakpoint() > 0.0 && candidate.breakpoint() < coarser_breakpoint {
+                coarser_breakpoint = candidate.breakpoint();
+                lods.push(candidate);
+            }
         }
+        lods.push(lod0);
+        lods.reverse();
 
         self.lods = lods;
     }
@@ -0,0 +1,166 @@
+//! Zero-copy access to binary molecule files through memory mapping.
+use crate::binary::{parse_molecule_header, LodEntry, MoleculeHeader};
+use crate::error::{Error, Result};
+use crate::BoundingBox;
+
+use memmap2::Mmap;
+use nalgebra_glm::Vec4;
+use std::mem::{align_of, size_of};
+use std::path::Path;
+
+/// Atoms are reinterpreted in place, which is only valid for the little-endian f32 layout of the file and an
+/// address aligned for `Vec4`.
+fn can_view_atoms(address: usize) -> bool {
+    cfg!(target_endian = "little") && address % align_of::<Vec4>() == 0
+}
+
+/// Binary molecule file mapped into memory. Atoms are read directly from the mapping without being copied.
+pub struct MappedMolecule {
+    mmap: Mmap,
+    header: MoleculeHeader,
+}
+
+impl MappedMolecule {
+    /// Maps the binary molecule file at `path`.
+    ///
+    /// The file must not be modified while it is mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(&path).map_err(|e| Error::io(&path, e))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| Error::io(&path, e))?;
+
+        let header = parse_molecule_header(&mmap).map_err(|e| Error::binary(&path, e))?;
+        if header.atoms_count == 0 {
+            return Err(Error::empty_molecule(&path));
+        }
+
+        debug_assert_eq!(size_of::<Vec4>(), 4 * size_of::<f32>());
+
+        if !can_view_atoms(mmap.as_ptr() as usize + header.atoms_offset) {
+            return Err(Error::binary(
+                &path,
+                "atoms can not be mapped on this platform".to_string(),
+            ));
+        }
+
+        Ok(Self { mmap, header })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.header.name
+    }
+
+    pub fn bounding_box(&self) -> &BoundingBox {
+        &self.header.bounding_box
+    }
+
+    pub fn lods(&self) -> &[LodEntry] {
+        &self.header.lods
+    }
+
+    /// Raw little-endian bytes of all atoms of all LODs, suitable for direct upload to the GPU.
+    pub fn atoms_bytes(&self) -> &[u8] {
+        let start = self.header.atoms_offset;
+        let end = start + self.header.atoms_count * size_of::<Vec4>();
+
+        &self.mmap[start..end]
+    }
+
+    /// All atoms of all LODs, LODs stored one after another.
+    pub fn atoms(&self) -> &[Vec4] {
+        let bytes = self.atoms_bytes();
+
+        // Safety: `open` checked alignment and endianness, and the header parser checked that the whole
+        // atom array lies within the mapping. `Vec4` is four consecutive `f32`.
+        unsafe {
+            std::slice::from_raw_parts(bytes.as_ptr() as *const Vec4, self.header.atoms_count)
+        }
+    }
+
+    /// Atoms of a single LOD.
+    pub fn lod_atoms(&self, lod: usize) -> &[Vec4] {
+        &self.atoms()[self.header.lods[lod].atoms.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::encode_molecule;
+    use crate::molecule::{Molecule, MoleculeLod};
+    use nalgebra_glm::vec4;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rpdb_mapped_{}_{}", std::process::id(), name))
+    }
+
+    fn molecule() -> Molecule {
+        let atoms: Vec<Vec4> = (0..7)
+            .map(|i| vec4(i as f32, 1.0, -(i as f32), 1.5))
+            .collect();
+
+        Molecule {
+            name: "M".to_string(),
+            bounding_box: crate::bounding_box(&atoms),
+            lods: vec![
+                MoleculeLod::new(atoms.clone(), 0.0),
+                MoleculeLod::new(atoms[2..5].to_vec(), 100.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn mapped_atoms_match_source() {
+        let molecule = molecule();
+        let path = temp_path("molecule.rpdb");
+        std::fs::write(&path, encode_molecule(&molecule)).unwrap();
+
+        let mapped = MappedMolecule::open(&path).unwrap();
+        assert_eq!(mapped.name(), molecule.name());
+        assert_eq!(mapped.lods().len(), molecule.lods().len());
+        for (i, lod) in molecule.lods().iter().enumerate() {
+            assert_eq!(mapped.lod_atoms(i), lod.atoms());
+            assert_eq!(mapped.lods()[i].breakpoint, lod.breakpoint());
+        }
+        assert_eq!(mapped.atoms_bytes().len(), 10 * size_of::<Vec4>());
+
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let bytes = encode_molecule(&molecule());
+        let header = parse_molecule_header(&bytes).unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[..4].copy_from_slice(b"RPDS");
+        let mut corrupted_count = bytes.clone();
+        corrupted_count[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        let truncated = &bytes[..header.atoms_offset + header.atoms_count * size_of::<Vec4>() - 1];
+
+        for (name, data) in [
+            ("magic.rpdb", &wrong_magic[..]),
+            ("count.rpdb", &corrupted_count[..]),
+            ("truncated.rpdb", truncated),
+        ]
+        .iter()
+        {
+            let path = temp_path(name);
+            std::fs::write(&path, data).unwrap();
+            assert!(MappedMolecule::open(&path).is_err(), "{}", name);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn misaligned_atoms_are_not_viewed() {
+        let bytes = encode_molecule(&molecule());
+        let header = parse_molecule_header(&bytes).unwrap();
+        assert_eq!(header.atoms_offset % 16, 0);
+
+        let address = bytes.as_ptr() as usize + header.atoms_offset;
+        assert!(!can_view_atoms(address + 1));
+        assert!(!can_view_atoms(address + 2));
+    }
+}
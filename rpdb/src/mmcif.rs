@@ -0,0 +1,461 @@
+//! Reader of the mmCIF/PDBx format.
+//!
+//! Only the first data block is read. Every item is stored under its full tag name, e.g. `_atom_site.Cartn_x`,
+//! as a column of values: single items have one value, loops have one value per row.
+use crate::atom::AtomRecord;
+use crate::error::{Error, Result};
+
+use nalgebra_glm::vec3;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Data block of a CIF file.
+#[derive(Clone, Debug, Default)]
+pub struct CifBlock {
+    pub name: String,
+    items: HashMap<String, Vec<String>>,
+}
+
+impl CifBlock {
+    /// Values of the item with full tag name `tag`. Tag names are case insensitive.
+    pub fn get(&self, tag: &str) -> Option<&[String]> {
+        self.items
+            .get(&tag.to_ascii_lowercase())
+            .map(|v| v.as_slice())
+    }
+
+    /// Values of the first of `tags` present in the block.
+    pub fn get_any(&self, tags: &[&str]) -> Option<&[String]> {
+        tags.iter().find_map(|tag| self.get(tag))
+    }
+
+    /// Whether the category, e.g. `_atom_site`, has at least one item.
+    pub fn has_category(&self, category: &str) -> bool {
+        let prefix = category.to_ascii_lowercase() + ".";
+        self.items.keys().any(|k| k.starts_with(&prefix))
+    }
+}
+
+/// Whether the value is one of CIF's placeholders for unknown (`?`) or inapplicable (`.`) values.
+pub fn is_null(value: &str) -> bool {
+    value == "?" || value == "."
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Value(String),
+    Tag(String),
+    Loop,
+    Data(String),
+}
+
+/// Splits CIF text into tokens together with the line they start at.
+fn tokenize(text: &str) -> std::result::Result<Vec<(usize, Token)>, (usize, String)> {
+    let mut tokens = Vec::new();
+
+    let mut lines = text.lines().enumerate();
+    while let Some((line_index, line)) = lines.next() {
+        let line_number = line_index + 1;
+
+        // Multi-line text field delimited by semicolons at the start of a line
+        if let Some(first) = line.strip_prefix(';') {
+            let mut value = first.to_string();
+            loop {
+                match lines.next() {
+                    Some((_, next)) if next.starts_with(';') => break,
+                    Some((_, next)) => {
+                        value.push('\n');
+                        value.push_str(next);
+                    }
+                    None => {
+                        return Err((line_number, "unterminated text field".to_string()));
+                    }
+                }
+            }
+            tokens.push((line_number, Token::Value(value.trim().to_string())));
+            continue;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+
+            if c.is_whitespace() {
+                i += 1;
+                continue;
+            }
+
+            if c == '#' {
+                break;
+            }
+
+            // Quoted value, which ends by the same quote followed by whitespace or the end of line
+            if c == '\'' || c == '"' {
+                let start = i + 1;
+                let mut end = start;
+                loop {
+                    if end >= chars.len() {
+                        return Err((line_number, "unterminated quoted value".to_string()));
+                    }
+                    if chars[end] == c && (end + 1 == chars.len() || chars[end + 1].is_whitespace())
+                    {
+                        break;
+                    }
+                    end += 1;
+                }
+                tokens.push((
+                    line_number,
+                    Token::Value(chars[start..end].iter().collect()),
+                ));
+                i = end + 1;
+                continue;
+            }
+
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let lower = word.to_ascii_lowercase();
+
+            let token = if lower.starts_with('_') {
+                Token::Tag(lower)
+            } else if lower == "loop_" {
+                Token::Loop
+            } else if lower.starts_with("data_") {
+                Token::Data(word[5..].to_string())
+            } else {
+                Token::Value(word)
+            };
+            tokens.push((line_number, token));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses the first data block of CIF text.
+pub fn parse(text: &str) -> std::result::Result<CifBlock, (usize, String)> {
+    let tokens = tokenize(text)?;
+    let mut block = CifBlock::default();
+
+    let mut i = 0;
+    let mut seen_data = false;
+    while i < tokens.len() {
+        let (line, token) = &tokens[i];
+        match token {
+            Token::Data(name) => {
+                if seen_data {
+                    break;
+                }
+                seen_data = true;
+                block.name = name.clone();
+                i += 1;
+            }
+            Token::Tag(tag) => match tokens.get(i + 1) {
+                Some((_, Token::Value(value))) => {
+                    block.items.insert(tag.clone(), vec![value.clone()]);
+                    i += 2;
+                }
+                _ => return Err((*line, format!("missing value of {}", tag))),
+            },
+            Token::Loop => {
+                i += 1;
+
+                let mut tags = Vec::new();
+                while let Some((_, Token::Tag(tag))) = tokens.get(i) {
+                    tags.push(tag.clone());
+                    i += 1;
+                }
+                if tags.is_empty() {
+                    return Err((*line, "loop without tags".to_string()));
+                }
+
+                let mut columns = vec![Vec::new(); tags.len()];
+                let mut count = 0;
+                while let Some((_, Token::Value(value))) = tokens.get(i) {
+                    columns[count % tags.len()].push(value.clone());
+                    count += 1;
+                    i += 1;
+                }
+                if count % tags.len() != 0 {
+                    return Err((
+                        *line,
+                        format!(
+                            "loop of {} has {} values, which is not a multiple of its {} columns",
+                            tags[0],
+                            count,
+                            tags.len()
+                        ),
+                    ));
+                }
+
+                for (tag, column) in tags.into_iter().zip(columns) {
+                    block.items.insert(tag, column);
+                }
+            }
+            Token::Value(value) => {
+                return Err((*line, format!("unexpected value {}", value)));
+            }
+        }
+    }
+
+    Ok(block)
+}
+
+/// Reads and parses the first data block of a CIF file.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<CifBlock> {
+    let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+    parse(&text).map_err(|(line, message)| Error::parse(&path, line, message))
+}
+
+/// Extracts atoms of the first model from the `_atom_site` category. Only the first alternate location of each atom is kept.
+pub fn atom_records(block: &CifBlock) -> std::result::Result<Vec<AtomRecord>, String> {
+    let column = |tags: &[&str]| -> std::result::Result<&[String], String> {
+        block
+            .get_any(tags)
+            .ok_or_else(|| format!("missing {}", tags[0]))
+    };
+
+    let x = column(&["_atom_site.cartn_x"])?;
+    let y = column(&["_atom_site.cartn_y"])?;
+    let z = column(&["_atom_site.cartn_z"])?;
+    let elements = column(&["_atom_site.type_symbol"])?;
+    let names = column(&["_atom_site.auth_atom_id", "_atom_site.label_atom_id"])?;
+    let residue_names = column(&["_atom_site.auth_comp_id", "_atom_site.label_comp_id"])?;
+    let chains = column(&["_atom_site.auth_asym_id", "_atom_site.label_asym_id"])?;
+    let label_chains = block.get("_atom_site.label_asym_id");
+    let residue_ids = block.get("_atom_site.auth_seq_id");
+    let label_residue_ids = block.get("_atom_site.label_seq_id");
+    let b_factors = block.get("_atom_site.b_iso_or_equiv");
+    let alt_ids = block.get("_atom_site.label_alt_id");
+    let models = block.get("_atom_site.pdbx_pdb_model_num");
+
+    // Columns are indexed by the row of the coordinates, so all of them must have one value per atom
+    let optional_columns = [
+        ("_atom_site.label_asym_id", label_chains),
+        ("_atom_site.auth_seq_id", residue_ids),
+        ("_atom_site.label_seq_id", label_residue_ids),
+        ("_atom_site.b_iso_or_equiv", b_factors),
+        ("_atom_site.label_alt_id", alt_ids),
+        ("_atom_site.pdbx_pdb_model_num", models),
+    ];
+    let columns = [
+        ("_atom_site.cartn_y", Some(y)),
+        ("_atom_site.cartn_z", Some(z)),
+        ("_atom_site.type_symbol", Some(elements)),
+        ("_atom_site.auth_atom_id", Some(names)),
+        ("_atom_site.auth_comp_id", Some(residue_names)),
+        ("_atom_site.auth_asym_id", Some(chains)),
+    ];
+    for (tag, values) in columns.iter().chain(optional_columns.iter()) {
+        if let Some(values) = values {
+            if values.len() != x.len() {
+                return Err(format!(
+                    "{} has {} values, expected {}",
+                    tag,
+                    values.len(),
+                    x.len()
+                ));
+            }
+        }
+    }
+
+    let parse_f32 = |value: &str, what: &str, row: usize| -> std::result::Result<f32, String> {
+        value
+            .parse::<f32>()
+            .map_err(|_| format!("invalid {} {:?} of atom {}", what, value, row + 1))
+    };
+
+    let first_model = models.and_then(|m| m.first()).cloned();
+    let first_alt = alt_ids
+        .and_then(|a| a.iter().find(|v| !is_null(v)))
+        .cloned();
+
+    let mut records = Vec::with_capacity(x.len());
+    for row in 0..x.len() {
+        if let (Some(models), Some(first_model)) = (models, &first_model) {
+            if &models[row] != first_model {
+                continue;
+            }
+        }
+
+        if let (Some(alt_ids), Some(first_alt)) = (alt_ids, &first_alt) {
+            if !is_null(&alt_ids[row]) && &alt_ids[row] != first_alt {
+                continue;
+            }
+        }
+
+        let residue_id = residue_ids
+            .map(|ids| ids[row].as_str())
+            .filter(|id| !is_null(id))
+            .or_else(|| label_residue_ids.map(|ids| ids[row].as_str()))
+            .filter(|id| !is_null(id))
+            .and_then(|id| id.parse::<i32>().ok())
+            .unwrap_or(0);
+
+        let b_factor = b_factors
+            .map(|b| b[row].as_str())
+            .filter(|b| !is_null(b))
+            .map(|b| parse_f32(b, "B-factor", row))
+            .transpose()?
+            .unwrap_or(0.0);
+
+        records.push(AtomRecord {
+            name: names[row].clone(),
+            element: elements[row].clone(),
+            residue_name: residue_names[row].clone(),
+            residue_id,
+            chain: chains[row].clone(),
+            label_chain: label_chains
+                .map(|c| c[row].clone())
+                .unwrap_or_else(|| chains[row].clone()),
+            position: vec3(
+                parse_f32(&x[row], "x coordinate", row)?,
+                parse_f32(&y[row], "y coordinate", row)?,
+                parse_f32(&z[row], "z coordinate", row)?,
+            ),
+            b_factor,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Reads atoms of the first model of an mmCIF file.
+pub fn read_atom_records<P: AsRef<Path>>(path: P) -> Result<(String, Vec<AtomRecord>)> {
+    let block = read(&path)?;
+    let records = atom_records(&block).map_err(|message| Error::parse(&path, 0, message))?;
+
+    Ok((block.name, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATOMS: &str = "data_1ABC
+#
+_entry.id 1ABC
+_struct.title 'Mother's little helper'
+_struct.pdbx_descriptor \"5\"-cap of \"it\"s\"
+_struct_keywords.text
+;first line
+second line
+;
+loop_
+_atom_site.group_PDB
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_alt_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.label_seq_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+_atom_site.B_iso_or_equiv
+_atom_site.auth_seq_id
+_atom_site.auth_asym_id
+_atom_site.pdbx_PDB_model_num
+ATOM N N . GLY A 1 1.0 2.0 3.0 10.5 5 B 1
+ATOM C \"O5'\" A GLY A 1 4.0 5.0 6.0 ? ? B 1
+ATOM C CA B GLY A 1 7.0 8.0 9.0 ? ? B 1
+HETATM O O . HOH C . 0.5 0.5 0.5 . . C 1
+ATOM N N . GLY A 1 1.0 2.0 3.0 10.5 5 B 2
+#
+data_2XYZ
+_entry.id 2XYZ
+";
+
+    #[test]
+    fn quoted_values_may_contain_quotes() {
+        let block = parse(ATOMS).unwrap();
+
+        assert_eq!(
+            block.get("_struct.title").unwrap(),
+            ["Mother's little helper"]
+        );
+        assert_eq!(
+            block.get("_struct.pdbx_descriptor").unwrap(),
+            ["5\"-cap of \"it\"s"]
+        );
+    }
+
+    #[test]
+    fn text_fields_span_lines() {
+        let block = parse(ATOMS).unwrap();
+
+        assert_eq!(
+            block.get("_struct_keywords.text").unwrap(),
+            ["first line\nsecond line"]
+        );
+        assert!(parse("data_x\n_a.b\n;never\nterminated\n").is_err());
+    }
+
+    #[test]
+    fn loops_are_read_as_columns() {
+        let block = parse(ATOMS).unwrap();
+
+        assert_eq!(block.get("_atom_site.Cartn_y").unwrap().len(), 5);
+        assert_eq!(
+            block.get("_ATOM_SITE.LABEL_ATOM_ID").unwrap(),
+            ["N", "O5'", "CA", "O", "N"]
+        );
+        assert!(block.has_category("_atom_site"));
+        assert!(parse("data_x\nloop_\n_a.b\n_a.c\n1 2 3\n").is_err());
+        assert!(parse("data_x\nloop_\n1 2\n").is_err());
+    }
+
+    #[test]
+    fn only_the_first_data_block_is_read() {
+        let block = parse(ATOMS).unwrap();
+
+        assert_eq!(block.name, "1ABC");
+        assert_eq!(block.get("_entry.id").unwrap(), ["1ABC"]);
+    }
+
+    #[test]
+    fn null_values_fall_back() {
+        let records = atom_records(&parse(ATOMS).unwrap()).unwrap();
+
+        // Second model and the second alternate location are left out
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].residue_id, 5);
+        assert_eq!(records[0].b_factor, 10.5);
+        assert_eq!(records[0].chain, "B");
+        assert_eq!(records[0].label_chain, "A");
+
+        // Author residue id `?` falls back to the label one, B-factor `?` to zero
+        assert_eq!(records[1].name, "O5'");
+        assert_eq!(records[1].residue_id, 1);
+        assert_eq!(records[1].b_factor, 0.0);
+
+        // Both residue ids `.`
+        assert_eq!(records[2].residue_name, "HOH");
+        assert_eq!(records[2].residue_id, 0);
+        assert_eq!(records[2].b_factor, 0.0);
+    }
+
+    #[test]
+    fn columns_of_different_length_are_rejected() {
+        let text = "data_x
+_atom_site.B_iso_or_equiv 5.0
+loop_
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+C CA GLY A 1.0 2.0 3.0
+C CB GLY A 4.0 5.0 6.0
+";
+        let error = atom_records(&parse(text).unwrap()).unwrap_err();
+
+        assert_eq!(error, "_atom_site.b_iso_or_equiv has 1 values, expected 2");
+    }
+}
@@ -3,13 +3,36 @@ use crate::BoundingBox;
 use nalgebra_glm::Vec4;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Chemical identity of an atom of the original molecule.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AtomAttributes {
+    pub name: String,
+    pub element: String,
+    pub chain: String,
+    pub residue_name: String,
+    pub residue_id: i32,
+    pub b_factor: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MoleculeLod {
     max_radius: f32,
 
     #[serde(default)]
     breakpoint: f32,
     atoms: Vec<Vec4>,
+
+    /// Attributes of each atom. Only present for LOD 0 of molecules converted from coordinate files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attributes: Vec<AtomAttributes>,
+
+    /// For each atom of LOD 0, index of the sphere of this LOD that represents it. Empty when unknown.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    representatives: Vec<u32>,
+
+    /// Geometric error of this LOD with respect to LOD 0, in the units of atom coordinates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<f32>,
 }
 
 impl MoleculeLod {
@@ -26,9 +49,34 @@ impl MoleculeLod {
             max_radius,
             breakpoint,
             atoms,
+            attributes: Vec::new(),
+            representatives: Vec::new(),
+            error: None,
         }
     }
 
+    /// Attaches attributes of atoms, one per atom.
+    pub fn with_attributes(mut self, attributes: Vec<AtomAttributes>) -> Self {
+        debug_assert_eq!(attributes.len(), self.atoms.len());
+        self.attributes = attributes;
+        self
+    }
+
+    /// Attaches the mapping from atoms of LOD 0 to the spheres of this LOD.
+    pub fn with_representatives(mut self, representatives: Vec<u32>) -> Self {
+        debug_assert!(representatives
+            .iter()
+            .all(|r| (*r as usize) < self.atoms.len()));
+        self.representatives = representatives;
+        self
+    }
+
+    /// Attaches the geometric error of this LOD.
+    pub fn with_error(mut self, error: f32) -> Self {
+        self.error = Some(error);
+        self
+    }
+
     pub fn max_radius(&self) -> f32 {
         self.max_radius
     }
@@ -44,8 +92,42 @@ impl MoleculeLod {
     pub fn set_breakpoint(&mut self, breakpoint: f32) {
         self.breakpoint = breakpoint;
     }
+
+    pub fn attributes(&self) -> Option<&[AtomAttributes]> {
+        if self.attributes.is_empty() {
+            None
+        } else {
+            Some(&self.attributes)
+        }
+    }
+
+    /// For each atom of LOD 0, index of the sphere of this LOD that represents it.
+    pub fn representatives(&self) -> Option<&[u32]> {
+        if self.representatives.is_empty() {
+            None
+        } else {
+            Some(&self.representatives)
+        }
+    }
+
+    /// Geometric error of this LOD with respect to LOD 0, if it was measured.
+    pub fn error(&self) -> Option<f32> {
+        self.error
+    }
+
+    /// Indices of atoms of LOD 0 represented by each sphere of this LOD.
+    pub fn represented_atoms(&self) -> Option<Vec<Vec<u32>>> {
+        let representatives = self.representatives()?;
+
+        let mut represented = vec![Vec::new(); self.atoms.len()];
+        for (atom, sphere) in representatives.iter().enumerate() {
+            represented[*sphere as usize].push(atom as u32);
+        }
+
+        Some(represented)
+    }
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Molecule {
     pub name: String,
     pub bounding_box: BoundingBox,
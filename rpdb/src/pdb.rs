@@ -0,0 +1,188 @@
+//! Reader of the legacy fixed-column PDB format.
+use crate::atom::AtomRecord;
+use crate::error::{Error, Result};
+
+use nalgebra_glm::vec3;
+use std::path::Path;
+
+/// Columns `start..end` (1-based, inclusive) of the line, trimmed. Missing columns are empty.
+fn columns(line: &str, start: usize, end: usize) -> &str {
+    let end = end.min(line.len());
+    if start > end {
+        return "";
+    }
+
+    line.get(start - 1..end).unwrap_or("").trim()
+}
+
+/// Element symbol derived from the atom name in columns 13-16, for files without the element columns. Names of
+/// one-letter elements start in column 14, those of two-letter elements such as FE or CL in column 13, except for
+/// hydrogens with four-character names such as HD21.
+fn element_from_name(line: &str) -> String {
+    let name = line.get(12..line.len().min(16)).unwrap_or("");
+    let long_hydrogen = name.starts_with(&['H', 'h'][..]) && name.trim_end().len() == 4;
+    let two_letters = name.starts_with(|c: char| c.is_ascii_alphabetic()) && !long_hydrogen;
+
+    let symbol: String = if two_letters {
+        name.chars()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .take(2)
+            .collect()
+    } else {
+        name.trim_start_matches(|c: char| c == ' ' || c.is_ascii_digit())
+            .chars()
+            .take(1)
+            .collect()
+    };
+
+    symbol.to_ascii_uppercase()
+}
+
+/// Parses `ATOM` and `HETATM` records of the first model. Only the first alternate location of each atom is kept.
+pub fn parse_atom_records(text: &str) -> std::result::Result<Vec<AtomRecord>, (usize, String)> {
+    let mut records = Vec::new();
+    let mut first_alt = None;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+
+        if line.starts_with("ENDMDL") {
+            break;
+        }
+
+        if !line.starts_with("ATOM") && !line.starts_with("HETATM") {
+            continue;
+        }
+
+        let alt = columns(line, 17, 17);
+        if !alt.is_empty() {
+            match &first_alt {
+                None => first_alt = Some(alt.to_string()),
+                Some(first) if first != alt => continue,
+                _ => {}
+            }
+        }
+
+        let coordinate = |start, end, what| {
+            columns(line, start, end)
+                .parse::<f32>()
+                .map_err(|_| (line_number, format!("invalid {} coordinate", what)))
+        };
+
+        let name = columns(line, 13, 16).to_string();
+        let element = match columns(line, 77, 78) {
+            "" => element_from_name(line),
+            element => element.to_ascii_uppercase(),
+        };
+        let chain = columns(line, 22, 22).to_string();
+
+        records.push(AtomRecord {
+            element,
+            residue_name: columns(line, 18, 20).to_string(),
+            residue_id: columns(line, 23, 26).parse::<i32>().unwrap_or(0),
+            label_chain: chain.clone(),
+            chain,
+            position: vec3(
+                coordinate(31, 38, "x")?,
+                coordinate(39, 46, "y")?,
+                coordinate(47, 54, "z")?,
+            ),
+            b_factor: columns(line, 61, 66).parse::<f32>().unwrap_or(0.0),
+            name,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Reads atoms of the first model of a PDB file.
+pub fn read_atom_records<P: AsRef<Path>>(path: P) -> Result<Vec<AtomRecord>> {
+    let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+    parse_atom_records(&text).map_err(|(line, message)| Error::parse(&path, line, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ATOM` record of atom `name`, given with its alignment in columns 13-16, at `x` with the element columns.
+    fn atom(name: &str, alt: &str, x: f32, element: &str) -> String {
+        format!(
+            "ATOM      1 {:<4}{:1}GLY A   1    {:8.3}{:8.3}{:8.3}  1.00 10.00          {:>2}",
+            name, alt, x, 2.0, 3.0, element
+        )
+    }
+
+    fn parse(lines: &[String]) -> Vec<AtomRecord> {
+        parse_atom_records(&lines.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn only_the_first_alternate_location_is_kept() {
+        let records = parse(&[
+            atom(" N  ", "", 1.0, "N"),
+            atom(" CA ", "A", 2.0, "C"),
+            atom(" CA ", "B", 2.5, "C"),
+            atom(" C  ", "B", 3.5, "C"),
+            atom(" O  ", "A", 4.0, "O"),
+        ]);
+
+        let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["N", "CA", "O"]);
+        assert_eq!(records[1].position.x, 2.0);
+    }
+
+    #[test]
+    fn records_after_the_first_model_are_ignored() {
+        let records = parse(&[
+            "MODEL        1".to_string(),
+            atom(" N  ", "", 1.0, "N"),
+            "ENDMDL".to_string(),
+            "MODEL        2".to_string(),
+            atom(" N  ", "", 5.0, "N"),
+            "ENDMDL".to_string(),
+        ]);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].position.x, 1.0);
+    }
+
+    #[test]
+    fn short_lines() {
+        // Lines ending after the coordinates have no B-factor and take their element from the name.
+        let line = atom("FE  ", "", 1.0, "");
+        let records = parse(&[line[..54].to_string()]);
+        assert_eq!(records[0].element, "FE");
+        assert_eq!(records[0].b_factor, 0.0);
+
+        // Lines ending within the coordinates are rejected with their line number.
+        let lines = [atom(" N  ", "", 1.0, "N"), line[..46].to_string()].join("\n");
+        let (line_number, message) = parse_atom_records(&lines).unwrap_err();
+        assert_eq!(line_number, 2);
+        assert_eq!(message, "invalid z coordinate");
+    }
+
+    #[test]
+    fn elements_without_element_columns_follow_the_name_alignment() {
+        let cases = [
+            (" CA ", "C"),
+            ("CA  ", "CA"),
+            ("FE  ", "FE"),
+            ("CL  ", "CL"),
+            (" OXT", "O"),
+            ("1HB ", "H"),
+            ("HD21", "H"),
+            ("HG  ", "HG"),
+        ];
+
+        for (name, element) in cases.iter() {
+            let records = parse(&[atom(name, "", 1.0, "")]);
+            assert_eq!(records[0].element, *element, "name {:?}", name);
+        }
+
+        // The element columns take precedence over the name.
+        let records = parse(&[atom(" CA ", "", 1.0, "CA")]);
+        assert_eq!(records[0].element, "CA");
+    }
+}
@@ -0,0 +1,186 @@
+//! Van der Waals radii of elements used when converting atoms to spheres.
+use crate::atom::AtomRecord;
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Radius of atoms of unknown elements.
+pub const UNKNOWN_RADIUS: f32 = 1.0;
+
+/// Radii by Alvarez, Dalton Trans. 42 (2013). Used for all elements missing in the other schemes.
+#[rustfmt::skip]
+const ALVAREZ: &[(&str, f32)] = &[
+    ("H", 1.20), ("HE", 1.43), ("LI", 2.12), ("BE", 1.98), ("B", 1.91), ("C", 1.77),
+    ("N", 1.66), ("O", 1.50), ("F", 1.46), ("NE", 1.58), ("NA", 2.50), ("MG", 2.51),
+    ("AL", 2.25), ("SI", 2.19), ("P", 1.90), ("S", 1.89), ("CL", 1.82), ("AR", 1.83),
+    ("K", 2.73), ("CA", 2.62), ("SC", 2.58), ("TI", 2.46), ("V", 2.42), ("CR", 2.45),
+    ("MN", 2.45), ("FE", 2.44), ("CO", 2.40), ("NI", 2.40), ("CU", 2.38), ("ZN", 2.39),
+    ("GA", 2.32), ("GE", 2.29), ("AS", 1.88), ("SE", 1.82), ("BR", 1.86), ("KR", 2.25),
+    ("RB", 3.21), ("SR", 2.84), ("Y", 2.75), ("ZR", 2.52), ("NB", 2.56), ("MO", 2.45),
+    ("TC", 2.44), ("RU", 2.46), ("RH", 2.44), ("PD", 2.15), ("AG", 2.53), ("CD", 2.49),
+    ("IN", 2.43), ("SN", 2.42), ("SB", 2.47), ("TE", 1.99), ("I", 2.04), ("XE", 2.06),
+    ("CS", 3.48), ("BA", 3.03), ("LA", 2.98), ("CE", 2.88), ("PR", 2.92), ("ND", 2.95),
+    ("SM", 2.90), ("EU", 2.87), ("GD", 2.83), ("TB", 2.79), ("DY", 2.87), ("HO", 2.81),
+    ("ER", 2.83), ("TM", 2.79), ("YB", 2.80), ("LU", 2.74), ("HF", 2.63), ("TA", 2.53),
+    ("W", 2.57), ("RE", 2.49), ("OS", 2.48), ("IR", 2.41), ("PT", 2.29), ("AU", 2.32),
+    ("HG", 2.45), ("TL", 2.47), ("PB", 2.60), ("BI", 2.54), ("AC", 2.80), ("TH", 2.93),
+    ("PA", 2.88), ("U", 2.71), ("NP", 2.82), ("PU", 2.81), ("AM", 2.83), ("CM", 3.05),
+    ("BK", 3.40), ("CF", 3.05), ("ES", 2.70),
+];
+
+/// Radii by Bondi, J. Phys. Chem. 68 (1964), extended for main group elements by Mantina et al., J. Phys. Chem. A 113 (2009).
+#[rustfmt::skip]
+const BONDI: &[(&str, f32)] = &[
+    ("H", 1.20), ("HE", 1.40), ("LI", 1.82), ("BE", 1.53), ("B", 1.92), ("C", 1.70),
+    ("N", 1.55), ("O", 1.52), ("F", 1.47), ("NE", 1.54), ("NA", 2.27), ("MG", 1.73),
+    ("AL", 1.84), ("SI", 2.10), ("P", 1.80), ("S", 1.80), ("CL", 1.75), ("AR", 1.88),
+    ("K", 2.75), ("CA", 2.31), ("NI", 1.63), ("CU", 1.40), ("ZN", 1.39), ("GA", 1.87),
+    ("GE", 2.11), ("AS", 1.85), ("SE", 1.90), ("BR", 1.85), ("KR", 2.02), ("RB", 3.03),
+    ("SR", 2.49), ("PD", 1.63), ("AG", 1.72), ("CD", 1.58), ("IN", 1.93), ("SN", 2.17),
+    ("SB", 2.06), ("TE", 2.06), ("I", 1.98), ("XE", 2.16), ("CS", 3.43), ("BA", 2.68),
+    ("PT", 1.72), ("AU", 1.66), ("HG", 1.55), ("TL", 1.96), ("PB", 2.02), ("BI", 2.07),
+    ("PO", 1.97), ("AT", 2.02), ("RN", 2.20), ("FR", 3.48), ("RA", 2.83), ("U", 1.86),
+];
+
+/// Radii historically used by rpdb for the most common elements of biomolecules.
+#[rustfmt::skip]
+const LEGACY: &[(&str, f32)] = &[
+    ("C", 1.548), ("H", 1.100), ("N", 1.400), ("O", 1.348), ("P", 1.880), ("S", 1.880),
+];
+
+/// United-atom radii of heavy atoms including their implicit hydrogens, after the ProtOr set
+/// of Tsai et al., J. Mol. Biol. 290 (1999). Carbons and oxygens are refined by atom name in `RadiusTable::radius`.
+#[rustfmt::skip]
+const UNITED_ATOM: &[(&str, f32)] = &[
+    ("C", 1.88), ("N", 1.64), ("O", 1.46), ("S", 1.77), ("P", 1.87), ("SE", 1.90),
+];
+
+/// Carbons of aromatic rings of standard residues, which carry at most one hydrogen.
+const AROMATIC_CARBONS: &[&str] = &[
+    "CG", "CD1", "CD2", "CE1", "CE2", "CE3", "CZ", "CZ2", "CZ3", "CH2",
+];
+const AROMATIC_RESIDUES: &[&str] = &["PHE", "TYR", "TRP", "HIS"];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RadiusScheme {
+    /// Historical rpdb radii for C, H, N, O, P and S, Alvarez radii for the rest.
+    Default,
+    Bondi,
+    Alvarez,
+    /// Radii of heavy atoms enlarged by their implicit hydrogens. Explicit hydrogens are left out.
+    UnitedAtom,
+}
+
+impl std::str::FromStr for RadiusScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "default" => Ok(RadiusScheme::Default),
+            "bondi" => Ok(RadiusScheme::Bondi),
+            "alvarez" => Ok(RadiusScheme::Alvarez),
+            "united-atom" | "united_atom" | "united" => Ok(RadiusScheme::UnitedAtom),
+            _ => Err(format!(
+                "unknown radius scheme {}, expected one of default, bondi, alvarez, united-atom",
+                s
+            )),
+        }
+    }
+}
+
+/// Radii of all elements of one scheme, with optional user overrides.
+#[derive(Clone, Debug)]
+pub struct RadiusTable {
+    scheme: RadiusScheme,
+    radii: HashMap<String, f32>,
+    overrides: HashMap<String, f32>,
+}
+
+impl RadiusTable {
+    pub fn new(scheme: RadiusScheme) -> Self {
+        let scheme_radii = match scheme {
+            RadiusScheme::Default => LEGACY,
+            RadiusScheme::Bondi => BONDI,
+            RadiusScheme::Alvarez => ALVAREZ,
+            RadiusScheme::UnitedAtom => UNITED_ATOM,
+        };
+
+        let radii = ALVAREZ
+            .iter()
+            .chain(scheme_radii.iter())
+            .map(|(element, radius)| (element.to_string(), *radius))
+            .collect();
+
+        Self {
+            scheme,
+            radii,
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn scheme(&self) -> RadiusScheme {
+        self.scheme
+    }
+
+    /// Overrides the radius of an element for all atoms of that element.
+    pub fn set(&mut self, element: &str, radius: f32) {
+        self.overrides
+            .insert(element.trim().to_ascii_uppercase(), radius);
+    }
+
+    /// Applies overrides from a RON map of element symbols to radii, e.g. `{"FE": 2.0, "ZN": 1.39}`.
+    pub fn load_overrides<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+        let overrides: HashMap<String, f32> =
+            ron::de::from_str(&file).map_err(|e| Error::ron(&path, e))?;
+
+        for (element, radius) in overrides {
+            self.set(&element, radius);
+        }
+
+        Ok(())
+    }
+
+    /// Radius of an element, regardless of the atom it belongs to.
+    pub fn element_radius(&self, element: &str) -> f32 {
+        let element = element.trim().to_ascii_uppercase();
+
+        self.overrides
+            .get(&element)
+            .or_else(|| self.radii.get(&element))
+            .copied()
+            .unwrap_or(UNKNOWN_RADIUS)
+    }
+
+    /// Radius of the atom. `None` if the atom is not represented in the scheme, i.e. hydrogens in united-atom radii.
+    pub fn radius(&self, record: &AtomRecord) -> Option<f32> {
+        let element = record.element.trim().to_ascii_uppercase();
+
+        if self.scheme != RadiusScheme::UnitedAtom || self.overrides.contains_key(&element) {
+            return Some(self.element_radius(&element));
+        }
+
+        let radius = match element.as_str() {
+            "H" | "D" => return None,
+            // Backbone carbonyl carbon
+            "C" if record.name == "C" => 1.61,
+            "C" if AROMATIC_RESIDUES.contains(&record.residue_name.as_str())
+                && AROMATIC_CARBONS.contains(&record.name.as_str()) =>
+            {
+                1.76
+            }
+            // Carbonyl oxygens
+            "O" if record.name == "O" || record.name == "OXT" => 1.42,
+            _ => self.element_radius(&element),
+        };
+
+        Some(radius)
+    }
+}
+
+impl Default for RadiusTable {
+    fn default() -> Self {
+        Self::new(RadiusScheme::Default)
+    }
+}
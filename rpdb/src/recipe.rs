@@ -0,0 +1,464 @@
+//! Recipes placing instances of molecules, as exported by cellPACK, and the selection of what is converted from them.
+use crate::error::{Error, Result};
+use crate::structure::{Compartment, Structure};
+
+use nalgebra_glm::{distance, max2, min2, quat, quat_to_mat4, translation, vec3, Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Reads a recipe of lines `name x y z qw qx qy qz`, each placing an instance of the molecule `name`.
+/// Lines with fewer fields are ignored.
+pub fn read_recipe<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Vec<Mat4>>> {
+    let file = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+    let mut molecules: HashMap<String, Vec<Mat4>> = HashMap::new();
+    for (index, line) in file.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 8 {
+            continue;
+        }
+
+        let mut numbers = [0.0f32; 7];
+        for (number, part) in numbers.iter_mut().zip(parts[1..8].iter()) {
+            *number = part.parse().map_err(|e| {
+                Error::parse(&path, index + 1, format!("invalid number {}: {}", part, e))
+            })?;
+        }
+
+        let position = vec3(numbers[0], numbers[1], numbers[2]);
+        let rotation = quat(numbers[4], numbers[5], numbers[6], numbers[3]);
+
+        molecules
+            .entry(parts[0].to_string())
+            .or_default()
+            .push(translation(&position) * quat_to_mat4(&rotation));
+    }
+
+    Ok(molecules)
+}
+
+/// Volume outside of which instances are left out.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum ClipVolume {
+    Box { min: Vec3, max: Vec3 },
+    Sphere { center: Vec3, radius: f32 },
+}
+
+impl ClipVolume {
+    pub fn contains(&self, point: &Vec3) -> bool {
+        match self {
+            ClipVolume::Box { min, max } => {
+                (0..3).all(|axis| point[axis] >= min[axis] && point[axis] <= max[axis])
+            }
+            ClipVolume::Sphere { center, radius } => distance(center, point) <= *radius,
+        }
+    }
+}
+
+/// Selection of the molecules and instances of a recipe.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RecipeConfig {
+    /// Names of the molecules to keep, all when empty.
+    pub include: Vec<String>,
+
+    /// Names of the molecules to leave out.
+    pub exclude: Vec<String>,
+
+    /// Instances are kept only with their position inside this volume.
+    pub clip: Option<ClipVolume>,
+
+    /// Instances are kept only with their position inside the box spanned by the positions of all instances
+    /// of this molecule, whether it is included or not.
+    pub clip_to: Option<String>,
+}
+
+fn contains_name(names: &[String], name: &str) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+fn position(instance: &Mat4) -> Vec3 {
+    instance.column(3).xyz()
+}
+
+impl RecipeConfig {
+    /// Whether instances of the molecule `name` are kept.
+    pub fn accepts(&self, name: &str) -> bool {
+        (self.include.is_empty() || contains_name(&self.include, name))
+            && !contains_name(&self.exclude, name)
+    }
+
+    /// Removes the molecules and instances not selected from `structure`, including molecules left without any
+    /// instances. Fails if the reference molecule of `clip_to` is not in the structure.
+    pub fn apply(&self, structure: &mut Structure) -> std::result::Result<(), String> {
+        let reference = match &self.clip_to {
+            Some(name) => {
+                let molecules = structure.flatten();
+                let instances = molecules
+                    .iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, instances)| instances)
+                    .filter(|instances| !instances.is_empty())
+                    .ok_or_else(|| format!("reference molecule {} is not in the recipe", name))?;
+
+                let first = position(&instances[0]);
+                let (min, max) = instances
+                    .iter()
+                    .fold((first, first), |(min, max), instance| {
+                        let p = position(instance);
+                        (min2(&min, &p), max2(&max, &p))
+                    });
+
+                Some(ClipVolume::Box { min, max })
+            }
+            None => None,
+        };
+
+        structure.retain_instances(|name, instance| {
+            let p = position(instance);
+            self.accepts(name)
+                && self
+                    .clip
+                    .iter()
+                    .chain(reference.iter())
+                    .all(|volume| volume.contains(&p))
+        });
+
+        Ok(())
+    }
+}
+
+/// Molecule of a recipe and the PDB file it is created from.
+#[derive(Clone, Debug)]
+pub struct Ingredient {
+    pub name: String,
+    pub pdb: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct JsonRecipe {
+    #[serde(default)]
+    cytoplasme: Option<JsonIngredients>,
+    #[serde(default)]
+    compartments: BTreeMap<String, JsonCompartment>,
+}
+
+#[derive(Deserialize)]
+struct JsonCompartment {
+    #[serde(default)]
+    surface: Option<JsonIngredients>,
+    #[serde(default)]
+    interior: Option<JsonIngredients>,
+    #[serde(default)]
+    compartments: BTreeMap<String, JsonCompartment>,
+}
+
+#[derive(Deserialize)]
+struct JsonIngredients {
+    #[serde(default)]
+    ingredients: BTreeMap<String, JsonIngredient>,
+}
+
+#[derive(Deserialize)]
+struct JsonIngredient {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    source: Option<JsonSource>,
+    #[serde(default)]
+    pdb: Option<String>,
+    /// Positions and rotations as quaternions `[x, y, z, w]`.
+    #[serde(default)]
+    results: Vec<([f32; 3], [f32; 4])>,
+}
+
+#[derive(Deserialize)]
+struct JsonSource {
+    #[serde(default)]
+    pdb: Option<String>,
+}
+
+/// PDB file of an ingredient, relative to the directory of the recipe. Identifiers without an extension and
+/// URLs refer to local `.pdb` files of the same name.
+fn ingredient_pdb(directory: &Path, name: &str, ingredient: &JsonIngredient) -> PathBuf {
+    let pdb = ingredient
+        .source
+        .as_ref()
+        .and_then(|source| source.pdb.as_deref())
+        .or(ingredient.pdb.as_deref())
+        .unwrap_or(name);
+    let pdb = pdb
+        .rsplit('/')
+        .next()
+        .filter(|_| pdb.contains("://"))
+        .unwrap_or(pdb);
+
+    let path = directory.join(pdb);
+    if path.extension().is_some() {
+        path
+    } else {
+        path.with_extension("pdb")
+    }
+}
+
+/// Adds instances of all ingredients to `molecules` and the ingredients not seen yet to `ingredients`.
+fn read_ingredients(
+    directory: &Path,
+    json: &JsonIngredients,
+    molecules: &mut HashMap<String, Vec<Mat4>>,
+    ingredients: &mut Vec<Ingredient>,
+) {
+    for (key, ingredient) in json.ingredients.iter() {
+        let name = ingredient.name.clone().unwrap_or_else(|| key.clone());
+
+        let instances = ingredient.results.iter().map(|(p, r)| {
+            translation(&vec3(p[0], p[1], p[2])) * quat_to_mat4(&quat(r[0], r[1], r[2], r[3]))
+        });
+        molecules.entry(name.clone()).or_default().extend(instances);
+
+        if !ingredients.iter().any(|i| i.name == name) {
+            ingredients.push(Ingredient {
+                pdb: ingredient_pdb(directory, &name, ingredient),
+                name,
+            });
+        }
+    }
+}
+
+/// Compartment with nested `surface` and `interior` compartments holding the ingredients on its surface and
+/// inside of it.
+fn read_compartment(
+    directory: &Path,
+    name: &str,
+    json: &JsonCompartment,
+    ingredients: &mut Vec<Ingredient>,
+) -> Compartment {
+    let mut compartment = Compartment::new(name);
+
+    for (part, part_json) in [("surface", &json.surface), ("interior", &json.interior)].iter() {
+        if let Some(part_json) = part_json {
+            let mut part = Compartment::new(part);
+            read_ingredients(directory, part_json, &mut part.molecules, ingredients);
+            compartment.compartments.push(part);
+        }
+    }
+
+    for (name, nested) in json.compartments.iter() {
+        let nested = read_compartment(directory, name, nested, ingredients);
+        compartment.compartments.push(nested);
+    }
+
+    compartment
+}
+
+/// Reads a cellPACK recipe or result in the JSON format. Ingredients of the cytoplasm are placed directly in the
+/// structure, each compartment becomes a compartment of the structure. Ingredients refer to their PDB files
+/// relative to the recipe.
+pub fn read_cellpack_json<P: AsRef<Path>>(path: P) -> Result<(Structure, Vec<Ingredient>)> {
+    let file = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+    let json: JsonRecipe =
+        serde_json::from_str(&file).map_err(|e| Error::parse(&path, e.line(), e.to_string()))?;
+
+    let directory = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+
+    Ok(cellpack_structure(directory, &json))
+}
+
+/// Structure and ingredients of a parsed cellPACK recipe whose PDB files are relative to `directory`.
+fn cellpack_structure(directory: &Path, json: &JsonRecipe) -> (Structure, Vec<Ingredient>) {
+    let mut structure = Structure::new(HashMap::new());
+    let mut ingredients = Vec::new();
+
+    if let Some(cytoplasme) = &json.cytoplasme {
+        read_ingredients(
+            directory,
+            cytoplasme,
+            &mut structure.molecules,
+            &mut ingredients,
+        );
+    }
+    for (name, compartment) in json.compartments.iter() {
+        let compartment = read_compartment(directory, name, compartment, &mut ingredients);
+        structure.compartments.push(compartment);
+    }
+
+    (structure, ingredients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instances(positions: &[[f32; 3]]) -> Vec<Mat4> {
+        positions
+            .iter()
+            .map(|p| translation(&vec3(p[0], p[1], p[2])))
+            .collect()
+    }
+
+    fn structure() -> Structure {
+        let mut molecules = HashMap::new();
+        molecules.insert(
+            "Spike".to_string(),
+            instances(&[[0.0, 0.0, 0.0], [10.0, 10.0, 10.0]]),
+        );
+        molecules.insert(
+            "M".to_string(),
+            instances(&[[1.0, 1.0, 1.0], [20.0, 0.0, 0.0]]),
+        );
+        molecules.insert("E".to_string(), instances(&[[5.0, 5.0, 5.0]]));
+
+        Structure::new(molecules)
+    }
+
+    fn names(structure: &Structure) -> Vec<String> {
+        let mut names: Vec<String> = structure.flatten().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn include_and_exclude_ignore_case() {
+        let config = RecipeConfig {
+            include: vec!["SPIKE".to_string(), "m".to_string()],
+            exclude: vec!["spike".to_string()],
+            ..RecipeConfig::default()
+        };
+        assert!(!config.accepts("Spike"));
+        assert!(config.accepts("M"));
+        assert!(!config.accepts("E"));
+
+        let mut structure = structure();
+        config.apply(&mut structure).unwrap();
+        assert_eq!(names(&structure), ["M"]);
+    }
+
+    #[test]
+    fn clip_to_missing_reference_fails() {
+        let config = RecipeConfig {
+            clip_to: Some("Nucleocapsid".to_string()),
+            ..RecipeConfig::default()
+        };
+
+        assert!(config.apply(&mut structure()).is_err());
+    }
+
+    #[test]
+    fn clip_to_keeps_instances_inside_reference() {
+        let config = RecipeConfig {
+            clip_to: Some("spike".to_string()),
+            ..RecipeConfig::default()
+        };
+
+        let mut structure = structure();
+        config.apply(&mut structure).unwrap();
+        assert_eq!(names(&structure), ["E", "M", "Spike"]);
+        assert_eq!(structure.molecules["M"].len(), 1);
+        assert_eq!(structure.molecules["Spike"].len(), 2);
+    }
+
+    #[test]
+    fn clip_volumes_include_their_boundary() {
+        let clip_box = ClipVolume::Box {
+            min: vec3(-1.0, 0.0, 2.0),
+            max: vec3(1.0, 4.0, 3.0),
+        };
+        assert!(clip_box.contains(&vec3(-1.0, 0.0, 2.0)));
+        assert!(clip_box.contains(&vec3(1.0, 4.0, 3.0)));
+        assert!(clip_box.contains(&vec3(0.0, 4.0, 2.5)));
+        assert!(!clip_box.contains(&vec3(0.0, 4.001, 2.5)));
+        assert!(!clip_box.contains(&vec3(-1.001, 2.0, 2.5)));
+
+        let sphere = ClipVolume::Sphere {
+            center: vec3(1.0, 1.0, 1.0),
+            radius: 2.0,
+        };
+        assert!(sphere.contains(&vec3(3.0, 1.0, 1.0)));
+        assert!(sphere.contains(&vec3(1.0, 1.0, -1.0)));
+        assert!(!sphere.contains(&vec3(1.0, 3.001, 1.0)));
+    }
+
+    #[test]
+    fn ingredient_pdb_resolves_urls_and_identifiers() {
+        let directory = Path::new("recipes");
+        let ingredient = |json: &str| serde_json::from_str::<JsonIngredient>(json).unwrap();
+
+        assert_eq!(
+            ingredient_pdb(
+                directory,
+                "Spike",
+                &ingredient(r#"{"source": {"pdb": "https://files.rcsb.org/download/6VXX.pdb"}}"#)
+            ),
+            Path::new("recipes/6VXX.pdb")
+        );
+        assert_eq!(
+            ingredient_pdb(directory, "Spike", &ingredient(r#"{"pdb": "6VXX"}"#)),
+            Path::new("recipes/6VXX.pdb")
+        );
+        assert_eq!(
+            ingredient_pdb(
+                directory,
+                "Spike",
+                &ingredient(r#"{"source": {"pdb": "spike.cif"}, "pdb": "6VXX"}"#)
+            ),
+            Path::new("recipes/spike.cif")
+        );
+        assert_eq!(
+            ingredient_pdb(directory, "Spike", &ingredient("{}")),
+            Path::new("recipes/Spike.pdb")
+        );
+    }
+
+    #[test]
+    fn cellpack_compartments_nest() {
+        let json: JsonRecipe = serde_json::from_str(
+            r#"{
+                "cytoplasme": {"ingredients": {"E": {"results": [[[1, 2, 3], [0, 0, 0, 1]]]}}},
+                "compartments": {
+                    "envelope": {
+                        "surface": {"ingredients": {
+                            "spike": {"name": "Spike", "pdb": "6VXX", "results": [[[0, 0, 0], [0, 0, 0, 1]]]}
+                        }},
+                        "interior": {"ingredients": {}},
+                        "compartments": {
+                            "nucleus": {"interior": {"ingredients": {
+                                "E": {"results": [[[4, 5, 6], [0, 0, 0, 1]], [[7, 8, 9], [0, 0, 0, 1]]]}
+                            }}}
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (structure, ingredients) = cellpack_structure(Path::new("recipes"), &json);
+
+        assert_eq!(structure.molecules["E"], instances(&[[1.0, 2.0, 3.0]]));
+        assert_eq!(structure.compartments.len(), 1);
+
+        let envelope = &structure.compartments[0];
+        assert_eq!(envelope.name, "envelope");
+        let parts: Vec<&str> = envelope
+            .compartments
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(parts, ["surface", "interior", "nucleus"]);
+        assert_eq!(envelope.compartments[0].molecules["Spike"].len(), 1);
+        assert!(envelope.compartments[1].molecules.is_empty());
+        assert_eq!(
+            structure
+                .compartment("envelope/nucleus/interior")
+                .unwrap()
+                .molecules["E"],
+            instances(&[[4.0, 5.0, 6.0], [7.0, 8.0, 9.0]])
+        );
+        assert_eq!(structure.flatten()["E"].len(), 3);
+
+        // Ingredients are listed once, however many compartments they appear in
+        let ingredient_names: Vec<&str> = ingredients.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(ingredient_names, ["E", "Spike"]);
+        assert_eq!(ingredients[1].pdb, Path::new("recipes/6VXX.pdb"));
+    }
+}
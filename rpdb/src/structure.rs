@@ -1,6 +1,326 @@
 use nalgebra_glm::Mat4;
 use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize)]
+use std::collections::HashMap;
+
+fn identity() -> Mat4 {
+    Mat4::identity()
+}
+
+fn find_compartment<'a>(compartments: &'a [Compartment], path: &str) -> Option<&'a Compartment> {
+    let mut names = path.splitn(2, '/');
+    let name = names.next()?;
+    let compartment = compartments.iter().find(|c| c.name == name)?;
+
+    match names.next() {
+        Some(rest) if !rest.is_empty() => find_compartment(&compartment.compartments, rest),
+        _ => Some(compartment),
+    }
+}
+
+fn find_compartment_mut<'a>(
+    compartments: &'a mut [Compartment],
+    path: &str,
+) -> Option<&'a mut Compartment> {
+    let mut names = path.splitn(2, '/');
+    let name = names.next()?;
+    let compartment = compartments.iter_mut().find(|c| c.name == name)?;
+
+    match names.next() {
+        Some(rest) if !rest.is_empty() => find_compartment_mut(&mut compartment.compartments, rest),
+        _ => Some(compartment),
+    }
+}
+
+/// Named part of a structure, such as an envelope or an interior, placed by its own transform relative to its parent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Compartment {
+    pub name: String,
+
+    /// Transform of the compartment relative to its parent.
+    #[serde(default = "identity")]
+    pub transform: Mat4,
+
+    /// Instances of molecules in the compartment, relative to the compartment.
+    #[serde(default)]
+    pub molecules: HashMap<String, Vec<Mat4>>,
+
+    /// Nested compartments.
+    #[serde(default)]
+    pub compartments: Vec<Compartment>,
+}
+
+impl Compartment {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            transform: Mat4::identity(),
+            molecules: HashMap::new(),
+            compartments: Vec::new(),
+        }
+    }
+
+    /// Adds instances of molecules in this compartment and all nested ones to `molecules`, transformed by `parent`.
+    pub fn flatten_into(&self, parent: &Mat4, molecules: &mut HashMap<String, Vec<Mat4>>) {
+        let transform = parent * self.transform;
+
+        for (name, instances) in self.molecules.iter() {
+            molecules
+                .entry(name.clone())
+                .or_default()
+                .extend(instances.iter().map(|instance| transform * instance));
+        }
+
+        for compartment in self.compartments.iter() {
+            compartment.flatten_into(&transform, molecules);
+        }
+    }
+
+    /// Keeps the instances of this compartment and all nested ones for which `f` returns true, given the name of
+    /// their molecule and their transform by `parent` as in `flatten_into`. Molecules left without instances
+    /// are removed.
+    pub fn retain_instances<F: FnMut(&str, &Mat4) -> bool>(&mut self, parent: &Mat4, f: &mut F) {
+        let transform = parent * self.transform;
+
+        for (name, instances) in self.molecules.iter_mut() {
+            instances.retain(|instance| f(name, &(transform * instance)));
+        }
+        self.molecules.retain(|_, instances| !instances.is_empty());
+
+        for compartment in self.compartments.iter_mut() {
+            compartment.retain_instances(&transform, f);
+        }
+    }
+
+    /// Finds a nested compartment by its path of names separated by `/`, e.g. `envelope/matrix`.
+    pub fn find(&self, path: &str) -> Option<&Compartment> {
+        find_compartment(&self.compartments, path)
+    }
+
+    /// Mutable variant of `find`.
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut Compartment> {
+        find_compartment_mut(&mut self.compartments, path)
+    }
+}
+
+/// Biological structure made of instances of molecules.
+///
+/// Instances are either placed directly in the structure, or in a hierarchy of compartments. Files without
+/// compartments load as before.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Structure {
-    pub molecules: std::collections::HashMap<String, Vec<Mat4>>,
+    /// Instances of molecules placed directly in the structure.
+    pub molecules: HashMap<String, Vec<Mat4>>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compartments: Vec<Compartment>,
+}
+
+impl Structure {
+    pub fn new(molecules: HashMap<String, Vec<Mat4>>) -> Self {
+        Self {
+            molecules,
+            compartments: Vec::new(),
+        }
+    }
+
+    /// Instances of all molecules of the structure and its compartments, transformed into the space of the structure.
+    pub fn flatten(&self) -> HashMap<String, Vec<Mat4>> {
+        let mut molecules = self.molecules.clone();
+
+        for compartment in self.compartments.iter() {
+            compartment.flatten_into(&Mat4::identity(), &mut molecules);
+        }
+
+        molecules
+    }
+
+    /// Keeps the instances for which `f` returns true, given the name of their molecule and their transform in the
+    /// space of the structure. Molecules left without instances are removed.
+    pub fn retain_instances<F: FnMut(&str, &Mat4) -> bool>(&mut self, mut f: F) {
+        for (name, instances) in self.molecules.iter_mut() {
+            instances.retain(|instance| f(name, instance));
+        }
+        self.molecules.retain(|_, instances| !instances.is_empty());
+
+        for compartment in self.compartments.iter_mut() {
+            compartment.retain_instances(&Mat4::identity(), &mut f);
+        }
+    }
+
+    /// Finds a compartment by its path of names separated by `/`, e.g. `envelope/matrix`.
+    pub fn compartment(&self, path: &str) -> Option<&Compartment> {
+        find_compartment(&self.compartments, path)
+    }
+
+    /// Mutable variant of `compartment`.
+    pub fn compartment_mut(&mut self, path: &str) -> Option<&mut Compartment> {
+        find_compartment_mut(&mut self.compartments, path)
+    }
+}
+
+/// Path of a molecule referenced by the structure at `structure_path`. Molecules are stored next to the structure,
+/// preferring the binary format over RON when both exist.
+pub fn molecule_path<P: AsRef<std::path::Path>>(
+    structure_path: P,
+    name: &str,
+) -> std::path::PathBuf {
+    let binary_path =
+        structure_path
+            .as_ref()
+            .with_file_name(format!("{}.{}", name, crate::binary::EXTENSION));
+
+    if binary_path.exists() {
+        binary_path
+    } else {
+        structure_path
+            .as_ref()
+            .with_file_name(name.to_owned() + ".ron")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::{rotation, translation, vec3, vec4, Vec4};
+
+    fn origin(transform: &Mat4) -> Vec4 {
+        transform * vec4(0.0, 0.0, 0.0, 1.0)
+    }
+
+    /// Envelope moved along x, holding a membrane moved along y, holding molecule `M` moved along z.
+    fn nested() -> Structure {
+        let mut membrane = Compartment::new("membrane");
+        membrane.transform = translation(&vec3(0.0, 2.0, 0.0));
+        membrane
+            .molecules
+            .insert("M".to_string(), vec![translation(&vec3(0.0, 0.0, 3.0))]);
+
+        let mut envelope = Compartment::new("envelope");
+        envelope.transform = translation(&vec3(1.0, 0.0, 0.0));
+        envelope.molecules.insert(
+            "E".to_string(),
+            vec![Mat4::identity(), translation(&vec3(-5.0, 0.0, 0.0))],
+        );
+        envelope.compartments.push(membrane);
+
+        let mut molecules = HashMap::new();
+        molecules.insert("S".to_string(), vec![Mat4::identity()]);
+        let mut structure = Structure::new(molecules);
+        structure.compartments.push(envelope);
+
+        structure
+    }
+
+    #[test]
+    fn flatten_composes_parent_child_and_instance() {
+        let mut structure = nested();
+
+        // Rotating the envelope also rotates the membrane inside it
+        let envelope_transform = translation(&vec3(1.0, 0.0, 0.0))
+            * rotation(std::f32::consts::FRAC_PI_2, &vec3(0.0, 0.0, 1.0));
+        structure.compartment_mut("envelope").unwrap().transform = envelope_transform;
+
+        let molecules = structure.flatten();
+        assert_eq!(molecules["S"], vec![Mat4::identity()]);
+        assert_eq!(molecules["E"].len(), 2);
+        assert_eq!(
+            molecules["M"],
+            vec![
+                envelope_transform
+                    * translation(&vec3(0.0, 2.0, 0.0))
+                    * translation(&vec3(0.0, 0.0, 3.0))
+            ]
+        );
+        assert!((origin(&molecules["M"][0]) - vec4(-1.0, 0.0, 3.0, 1.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn retain_instances_sees_flattened_transforms_and_removes_empty_molecules() {
+        let mut structure = nested();
+
+        // Keep only instances with a positive x in the space of the structure
+        structure.retain_instances(|_, instance| origin(instance).x > 0.5);
+
+        assert!(structure.molecules.is_empty());
+        let envelope = structure.compartment("envelope").unwrap();
+        assert_eq!(envelope.molecules["E"], vec![Mat4::identity()]);
+        assert_eq!(
+            structure
+                .compartment("envelope/membrane")
+                .unwrap()
+                .molecules
+                .len(),
+            1
+        );
+
+        structure.retain_instances(|name, _| name != "M");
+        assert!(structure
+            .compartment("envelope/membrane")
+            .unwrap()
+            .molecules
+            .is_empty());
+        assert_eq!(structure.flatten().len(), 1);
+    }
+
+    #[test]
+    fn compartment_paths() {
+        let mut structure = nested();
+
+        assert_eq!(structure.compartment("envelope").unwrap().name, "envelope");
+        assert_eq!(
+            structure.compartment("envelope/membrane").unwrap().name,
+            "membrane"
+        );
+        assert_eq!(structure.compartment("envelope/").unwrap().name, "envelope");
+        assert_eq!(
+            structure.compartment("envelope/membrane/").unwrap().name,
+            "membrane"
+        );
+        assert!(structure.compartment("").is_none());
+        assert!(structure.compartment("/").is_none());
+        assert!(structure.compartment("membrane").is_none());
+        assert!(structure.compartment("envelope/matrix").is_none());
+        assert!(structure.compartment("envelope//membrane").is_none());
+        assert!(structure.compartment("envelope/membrane/matrix").is_none());
+
+        let envelope = structure.compartment("envelope").unwrap();
+        assert_eq!(envelope.find("membrane").unwrap().name, "membrane");
+        assert!(envelope.find("envelope").is_none());
+
+        structure.compartment_mut("envelope/membrane").unwrap().name = "matrix".to_string();
+        assert!(structure.compartment("envelope/matrix").is_some());
+        assert!(structure
+            .compartment_mut("envelope")
+            .unwrap()
+            .find_mut("membrane")
+            .is_none());
+    }
+
+    #[test]
+    fn flat_ron_without_compartments_loads() {
+        let instance = translation(&vec3(1.0, 2.0, 3.0));
+        let text = format!(
+            "(molecules: {{\"S\": [{}]}})",
+            ron::ser::to_string(&instance).unwrap()
+        );
+
+        let structure: Structure = ron::de::from_str(&text).unwrap();
+        assert!(structure.compartments.is_empty());
+        assert_eq!(structure.molecules["S"], vec![instance]);
+        assert_eq!(structure.flatten(), structure.molecules);
+
+        // Written back without the empty compartments
+        assert!(!ron::ser::to_string(&structure)
+            .unwrap()
+            .contains("compartments"));
+    }
+
+    #[test]
+    fn compartment_defaults() {
+        let compartment: Compartment = ron::de::from_str("(name: \"envelope\")").unwrap();
+
+        assert_eq!(compartment.transform, Mat4::identity());
+        assert!(compartment.molecules.is_empty());
+        assert!(compartment.compartments.is_empty());
+    }
 }
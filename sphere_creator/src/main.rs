@@ -63,10 +63,15 @@ fn main() {
 
     let factor = 300.0;
 
-    let mut map: HashMap<String, Vec<Mat4>> = HashMap::new();
+    let mut outer = Compartment::new("OUTER");
+    let mut inner = Compartment::new("INNER");
     for i in 1..=40 {
-        map.insert("OUTER_".to_string() + &i.to_string(), vec![]);
-        map.insert("INNER_".to_string() + &i.to_string(), vec![]);
+        outer
+            .molecules
+            .insert("OUTER_".to_string() + &i.to_string(), vec![]);
+        inner
+            .molecules
+            .insert("INNER_".to_string() + &i.to_string(), vec![]);
     }
     // let mut main_shell = Vec::new();
     for x in (0..360).step_by(6) {
@@ -93,7 +98,7 @@ fn main() {
 
             let i = rng.gen_range(10, 20);
             let name = "OUTER_".to_string() + &i.to_string();
-            map.get_mut(&name).unwrap().push(model_matrix);
+            outer.molecules.get_mut(&name).unwrap().push(model_matrix);
 
             // main_shell.push(model_matrix);
 
@@ -130,17 +135,18 @@ fn main() {
 
         let i = rng.gen_range(10, 20);
         let name = "INNER_".to_string() + &i.to_string();
-        map.get_mut(&name).unwrap().push(model_matrix);
+        inner.molecules.get_mut(&name).unwrap().push(model_matrix);
 
         // molecules_inner.push(model_matrix);
     }
     // map.insert("C_INNER".to_string(), molecules_inner);
     // map.insert("C_SHELL".to_string(), main_shell);
 
-    map.retain(|_, v| !v.is_empty());
+    outer.molecules.retain(|_, v| !v.is_empty());
+    inner.molecules.retain(|_, v| !v.is_empty());
 
-
-    let structure = Structure { molecules: map };
+    let mut structure = Structure::new(HashMap::new());
+    structure.compartments = vec![outer, inner];
     let data = ron::ser::to_string(&structure).expect("Could not serialize the structure.");
     std::fs::write(out_path, data).expect("Could not write the structure.");
 }